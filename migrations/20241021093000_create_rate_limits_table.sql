@@ -0,0 +1,6 @@
+-- Add migration script here
+CREATE TABLE rate_limits(
+    key TEXT PRIMARY KEY,
+    window_started_at timestamptz NOT NULL,
+    hits INTEGER NOT NULL
+);
@@ -0,0 +1,4 @@
+-- Keyset pagination of the admin subscriber list, one index per sort field.
+CREATE INDEX subscriptions_subscribed_at_id_idx ON subscriptions (subscribed_at, id);
+CREATE INDEX subscriptions_email_id_idx ON subscriptions (email, id);
+CREATE INDEX subscriptions_name_id_idx ON subscriptions (name, id);
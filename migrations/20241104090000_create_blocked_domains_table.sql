@@ -0,0 +1,5 @@
+-- Add migration script here
+CREATE TABLE blocked_domains(
+    domain TEXT PRIMARY KEY,
+    blocked_at timestamptz NOT NULL
+);
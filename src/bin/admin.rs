@@ -0,0 +1,213 @@
+use std::io::{BufRead, IsTerminal};
+use std::path::PathBuf;
+
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use secrecy::Secret;
+use zero2prod::{
+    authentication::{change_password, create_user},
+    blocked_domains::stored_blocked_domains,
+    configuration::{configuration_directory, load_configuration, Settings},
+    domain::SubscriberEmail,
+    maintenance::{purge_stale_pending, resend_pending_confirmations},
+    migration::{migrate_database, MigrationMode},
+    secrets::{configured_providers, resolve_secrets},
+    startup::{get_connection_pool, ApplicationBaseUrl},
+    subscriber_import::{import_subscribers, ImportOptions, ImportStatus},
+    telemetry::{get_subscriber, init_redaction, init_subscriber},
+};
+
+/// Administrative tasks for the newsletter service.
+///
+/// Reads the same configuration as the server, including `APP_ENVIRONMENT`,
+/// `APP_CONFIG_DIR` and `APP_` overrides.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    /// Read the configuration files from this directory instead.
+    #[arg(long, global = true)]
+    config_dir: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Apply pending database migrations.
+    Migrate,
+    /// Create an admin user. The password is read from stdin.
+    CreateAdmin { username: String },
+    /// Set a new password for an admin user. The password is read from stdin.
+    ResetAdminPassword { username: String },
+    /// Send a test email through the configured email provider.
+    SendTestEmail { recipient: String },
+    /// Send the confirmation email again to pending subscribers.
+    ResendConfirmations {
+        /// Only subscribers that have been pending for at least this long.
+        #[arg(long, default_value_t = 24)]
+        older_than_hours: i64,
+    },
+    /// Delete subscribers that never confirmed their subscription.
+    PurgePending {
+        #[arg(long, default_value_t = 30)]
+        older_than_days: i64,
+    },
+    /// Print the effective configuration, with secrets redacted.
+    PrintConfig,
+    /// Import subscribers from a CSV file with `email` and `name` columns.
+    ImportSubscribers {
+        file: PathBuf,
+        #[arg(long, value_enum, default_value_t)]
+        status: ImportStatus,
+        /// Email pending subscribers a confirmation link.
+        #[arg(long)]
+        send_confirmation: bool,
+        /// Rows written per transaction.
+        #[arg(long, default_value_t = 500)]
+        batch_size: usize,
+    },
+}
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    let subscriber = get_subscriber(
+        "zero2prod-admin".into(),
+        "warn".into(),
+        std::io::stderr,
+        None,
+    );
+    init_subscriber(subscriber);
+
+    let cli = Cli::parse();
+    let configuration_dir = cli
+        .config_dir
+        .clone()
+        .unwrap_or_else(configuration_directory);
+    let mut configuration =
+        load_configuration(&configuration_dir).context("Failed to read configuration.")?;
+    let providers = configured_providers(&configuration.secrets);
+    resolve_secrets(&mut configuration, &providers)
+        .await
+        .context("Failed to resolve secrets.")?;
+    init_redaction(&configuration.tracing.redaction);
+    let pool = get_connection_pool(&configuration.database);
+
+    match cli.command {
+        Command::Migrate => {
+            migrate_database(&configuration.database, MigrationMode::Apply).await?;
+            println!("Database is up to date.");
+        }
+        Command::CreateAdmin { username } => {
+            let user_id = create_user(&username, read_password()?, &pool).await?;
+            println!("Created admin {} ({}).", username, user_id);
+        }
+        Command::ResetAdminPassword { username } => {
+            if !change_password(&username, read_password()?, &pool).await? {
+                anyhow::bail!("There is no admin called {}.", username);
+            }
+            println!("Changed the password of {}.", username);
+        }
+        Command::SendTestEmail { recipient } => {
+            let recipient = SubscriberEmail::parse(recipient).map_err(anyhow::Error::msg)?;
+            configuration
+                .email_client
+                .client()
+                .send_email(
+                    "test",
+                    &recipient,
+                    "Test email",
+                    "<p>Email delivery is working.</p>",
+                    "Email delivery is working.",
+                )
+                .await
+                .context("Failed to send the test email.")?;
+            println!("Sent a test email to {}.", recipient);
+        }
+        Command::ResendConfirmations { older_than_hours } => {
+            let report = resend_pending_confirmations(
+                &pool,
+                &configuration.email_client.client(),
+                &ApplicationBaseUrl(configuration.application.link_base().to_owned()),
+                chrono::Duration::hours(older_than_hours),
+            )
+            .await?;
+            println!(
+                "Resent {} confirmation emails, {} failed.",
+                report.sent, report.failed
+            );
+        }
+        Command::PurgePending { older_than_days } => {
+            let deleted =
+                purge_stale_pending(&pool, chrono::Duration::days(older_than_days)).await?;
+            println!("Deleted {} stale pending subscribers.", deleted);
+        }
+        Command::PrintConfig => print_config(&configuration),
+        Command::ImportSubscribers {
+            file,
+            status,
+            send_confirmation,
+            batch_size,
+        } => {
+            let options = ImportOptions {
+                batch_size: batch_size.max(1),
+                ..ImportOptions::new(status, send_confirmation)?
+            };
+            let domain_policy = configuration.domain_policy.policy();
+            domain_policy.set_blocked(
+                &stored_blocked_domains(&pool)
+                    .await
+                    .context("Failed to load the blocked domains.")?,
+            );
+            let input = tokio::fs::File::open(&file)
+                .await
+                .with_context(|| format!("Failed to open {}.", file.display()))?;
+            let report = import_subscribers(
+                input,
+                &pool,
+                &domain_policy,
+                &configuration.email_client.client(),
+                &ApplicationBaseUrl(configuration.application.link_base().to_owned()),
+                &options,
+            )
+            .await?;
+
+            for duplicate in &report.duplicates {
+                println!("line {}: duplicate: {}", duplicate.line, duplicate.email);
+            }
+            for error in &report.errors {
+                println!("line {}: {}: {}", error.line, error.code, error.message);
+            }
+            println!(
+                "Imported {} subscribers, skipped {} duplicates, {} errors.",
+                report.imported,
+                report.duplicates.len(),
+                report.errors.len()
+            );
+            if let Some(e) = report.read_error {
+                anyhow::bail!("Stopped reading {} early: {}", file.display(), e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Prompt without echo on a terminal, otherwise read the first line, so
+/// that passwords never end up in the shell history.
+fn read_password() -> Result<Secret<String>, anyhow::Error> {
+    let password = if std::io::stdin().is_terminal() {
+        rpassword::prompt_password("Password: ")?
+    } else {
+        let mut line = String::new();
+        std::io::stdin().lock().read_line(&mut line)?;
+        line.trim_end_matches(['\r', '\n']).to_string()
+    };
+    if password.is_empty() {
+        anyhow::bail!("The password cannot be empty.");
+    }
+    Ok(Secret::new(password))
+}
+
+fn print_config(configuration: &Settings) {
+    // `Secret` redacts itself in `Debug` output.
+    println!("{:#?}", configuration);
+}
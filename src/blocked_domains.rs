@@ -0,0 +1,69 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use sqlx::PgPool;
+
+use crate::domain::{normalise_domain, DomainPolicy};
+use crate::shutdown::Shutdown;
+
+/// How often the domains blocked by other instances are picked up.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The domains blocked through the admin API, on top of the configured
+/// deny list.
+pub async fn stored_blocked_domains(pool: &PgPool) -> Result<Vec<String>, sqlx::Error> {
+    let rows = sqlx::query!("SELECT domain FROM blocked_domains")
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.into_iter().map(|r| r.domain).collect())
+}
+
+/// Returns `false` if the domain was already stored.
+pub async fn store_blocked_domain(pool: &PgPool, domain: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO blocked_domains (domain, blocked_at)
+        VALUES ($1, $2)
+        ON CONFLICT (domain) DO NOTHING
+        "#,
+        normalise_domain(domain),
+        Utc::now()
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Returns `false` if the domain was not stored.
+pub async fn delete_blocked_domain(pool: &PgPool, domain: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        "DELETE FROM blocked_domains WHERE domain = $1",
+        normalise_domain(domain)
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Background worker loading the blocked domains into `domain_policy`,
+/// until shutdown.
+pub async fn refresh_blocked_domains(
+    domain_policy: Arc<DomainPolicy>,
+    pool: PgPool,
+    shutdown: Shutdown,
+) {
+    let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+    // The first tick is immediate, they were just loaded at startup.
+    interval.tick().await;
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = interval.tick() => match stored_blocked_domains(&pool).await {
+                Ok(blocked) => domain_policy.set_blocked(&blocked),
+                Err(e) => tracing::warn!(error.cause_chain = ?e, "Failed to load the blocked domains"),
+            },
+        }
+    }
+    tracing::info!("Stopped refreshing the blocked domains");
+}
@@ -0,0 +1,347 @@
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use secrecy::ExposeSecret;
+use sha2::{Digest, Sha256};
+
+use crate::configuration::BotDefenceSettings;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Why a submission was classified as automated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BotSignal {
+    Honeypot,
+    MissingFormToken,
+    InvalidFormToken,
+    ReplayedFormToken,
+    TooFast,
+    ProofOfWork,
+}
+
+impl BotSignal {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BotSignal::Honeypot => "honeypot",
+            BotSignal::MissingFormToken => "missing_form_token",
+            BotSignal::InvalidFormToken => "invalid_form_token",
+            BotSignal::ReplayedFormToken => "replayed_form_token",
+            BotSignal::TooFast => "too_fast",
+            BotSignal::ProofOfWork => "proof_of_work",
+        }
+    }
+}
+
+/// The anti-bot fields of a signup form.
+pub struct Submission<'a> {
+    pub honeypot: Option<&'a str>,
+    pub form_token: Option<&'a str>,
+    pub proof_of_work: Option<&'a str>,
+    pub email: &'a str,
+}
+
+/// Privacy-friendly bot checks for the subscribe form.
+///
+/// Forms embed a signed timestamp obtained from `/subscriptions/form_token`,
+/// so submissions that come back faster than a human could type, or with a
+/// forged token, can be told apart. Optionally the client must also find a
+/// nonce whose hash with the token and email has enough leading zero bits.
+///
+/// A token is used once: replaying it, e.g. with the proof of work
+/// already solved, is a signal too. Used tokens are remembered by this
+/// instance only, until they expire.
+pub struct BotDefence {
+    settings: BotDefenceSettings,
+    /// The issue time and nonce of the tokens used so far, oldest first.
+    used_tokens: Mutex<BTreeSet<(u64, String)>>,
+}
+
+/// A form token that passed [`BotDefence::check`], to be used up with
+/// [`BotDefence::use_token`] once the submission is accepted.
+#[derive(Debug, PartialEq, Eq)]
+pub struct FormToken {
+    issued_at: u64,
+    nonce: String,
+}
+
+impl BotDefence {
+    pub fn new(settings: BotDefenceSettings) -> Self {
+        Self {
+            settings,
+            used_tokens: Mutex::new(BTreeSet::new()),
+        }
+    }
+
+    pub fn proof_of_work_bits(&self) -> u8 {
+        self.settings.proof_of_work_bits
+    }
+
+    pub fn issue_form_token(&self) -> String {
+        self.sign_form_token(unix_now())
+    }
+
+    fn sign_form_token(&self, issued_at: u64) -> String {
+        let nonce: String = std::iter::repeat_with(|| thread_rng().sample(Alphanumeric))
+            .map(char::from)
+            .take(16)
+            .collect();
+        let payload = format!("{}.{}", issued_at, nonce);
+        let signature = URL_SAFE_NO_PAD.encode(self.mac(&payload).finalize().into_bytes());
+        format!("{}.{}", payload, signature)
+    }
+
+    fn mac(&self, payload: &str) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(self.settings.form_secret.expose_secret().as_bytes())
+                .expect("HMAC can take a key of any size");
+        mac.update(payload.as_bytes());
+        mac
+    }
+
+    /// Run every check, recording the first one that fails.
+    ///
+    /// The form token, if any, is not used up: a person correcting a
+    /// rejected submission resends the same form.
+    pub fn check(&self, submission: &Submission<'_>) -> Result<Option<FormToken>, BotSignal> {
+        self.evaluate(submission).inspect_err(record_rejection)
+    }
+
+    /// Mark the token of an accepted submission as used.
+    pub fn use_token(&self, token: Option<FormToken>) -> Result<(), BotSignal> {
+        let Some(FormToken { issued_at, nonce }) = token else {
+            return Ok(());
+        };
+        let mut used = self.used_tokens.lock().unwrap();
+        // Expired tokens are refused anyway.
+        let oldest_valid = unix_now().saturating_sub(self.settings.max_form_age_seconds);
+        *used = used.split_off(&(oldest_valid, String::new()));
+        if used.insert((issued_at, nonce)) {
+            Ok(())
+        } else {
+            record_rejection(&BotSignal::ReplayedFormToken);
+            Err(BotSignal::ReplayedFormToken)
+        }
+    }
+
+    fn evaluate(&self, submission: &Submission<'_>) -> Result<Option<FormToken>, BotSignal> {
+        if submission.honeypot.is_some_and(|v| !v.trim().is_empty()) {
+            return Err(BotSignal::Honeypot);
+        }
+
+        let form_token = match submission.form_token {
+            Some(token) => token,
+            None if self.settings.require_form_token || self.proof_of_work_bits() > 0 => {
+                return Err(BotSignal::MissingFormToken)
+            }
+            None => return Ok(None),
+        };
+        let (issued_at, nonce) = self
+            .verify_form_token(form_token)
+            .ok_or(BotSignal::InvalidFormToken)?;
+        let age = unix_now().saturating_sub(issued_at);
+        if age > self.settings.max_form_age_seconds {
+            return Err(BotSignal::InvalidFormToken);
+        }
+        if age < self.settings.min_fill_seconds {
+            return Err(BotSignal::TooFast);
+        }
+
+        let bits = self.proof_of_work_bits();
+        if bits > 0 {
+            let nonce = submission.proof_of_work.ok_or(BotSignal::ProofOfWork)?;
+            if !verify_proof_of_work(form_token, submission.email, nonce, bits) {
+                return Err(BotSignal::ProofOfWork);
+            }
+        }
+        let token = FormToken {
+            issued_at,
+            nonce: nonce.to_owned(),
+        };
+        let used = self.used_tokens.lock().unwrap();
+        if used.contains(&(token.issued_at, token.nonce.clone())) {
+            return Err(BotSignal::ReplayedFormToken);
+        }
+        Ok(Some(token))
+    }
+
+    /// Returns the issue time and nonce of a token carrying a valid
+    /// signature.
+    fn verify_form_token<'a>(&self, token: &'a str) -> Option<(u64, &'a str)> {
+        let (payload, signature) = token.rsplit_once('.')?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        self.mac(payload).verify_slice(&signature).ok()?;
+        let (issued_at, nonce) = payload.split_once('.')?;
+        Some((issued_at.parse().ok()?, nonce))
+    }
+}
+
+fn record_rejection(signal: &BotSignal) {
+    metrics::counter!("bot_submissions_rejected_total", "signal" => signal.as_str()).increment(1);
+}
+
+/// Check a hashcash-style stamp: `SHA-256(form_token:email:nonce)` must
+/// start with at least `bits` zero bits.
+pub fn verify_proof_of_work(form_token: &str, email: &str, nonce: &str, bits: u8) -> bool {
+    let digest = Sha256::new()
+        .chain_update(form_token)
+        .chain_update(":")
+        .chain_update(email.to_lowercase())
+        .chain_update(":")
+        .chain_update(nonce)
+        .finalize();
+    leading_zero_bits(&digest) >= u32::from(bits)
+}
+
+fn leading_zero_bits(bytes: &[u8]) -> u32 {
+    let mut count = 0;
+    for byte in bytes {
+        if *byte == 0 {
+            count += 8;
+        } else {
+            count += byte.leading_zeros();
+            break;
+        }
+    }
+    count
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify_proof_of_work, BotDefence, BotSignal, Submission};
+    use crate::configuration::BotDefenceSettings;
+    use secrecy::Secret;
+
+    fn defence(min_fill_seconds: u64, proof_of_work_bits: u8) -> BotDefence {
+        BotDefence::new(BotDefenceSettings {
+            form_secret: Secret::new("secret".into()),
+            require_form_token: true,
+            min_fill_seconds,
+            max_form_age_seconds: 3600,
+            proof_of_work_bits,
+        })
+    }
+
+    fn submission<'a>(
+        form_token: Option<&'a str>,
+        proof_of_work: Option<&'a str>,
+    ) -> Submission<'a> {
+        Submission {
+            honeypot: None,
+            form_token,
+            proof_of_work,
+            email: "ursula@example.com",
+        }
+    }
+
+    #[test]
+    fn a_filled_honeypot_is_rejected() {
+        let defence = defence(0, 0);
+        let token = defence.issue_form_token();
+        let mut submission = submission(Some(&token), None);
+        submission.honeypot = Some("http://spam.example");
+        assert_eq!(defence.check(&submission), Err(BotSignal::Honeypot));
+    }
+
+    #[test]
+    fn a_valid_token_is_accepted() {
+        let defence = defence(0, 0);
+        let token = defence.issue_form_token();
+        assert!(defence.check(&submission(Some(&token), None)).is_ok());
+    }
+
+    #[test]
+    fn a_token_is_used_once() {
+        let defence = defence(0, 0);
+        let token = defence.issue_form_token();
+        let checked = defence.check(&submission(Some(&token), None)).unwrap();
+        let again = defence.check(&submission(Some(&token), None)).unwrap();
+        assert!(defence.use_token(checked).is_ok());
+        assert_eq!(
+            defence.check(&submission(Some(&token), None)),
+            Err(BotSignal::ReplayedFormToken)
+        );
+        assert_eq!(defence.use_token(again), Err(BotSignal::ReplayedFormToken));
+    }
+
+    #[test]
+    fn expired_used_tokens_are_forgotten() {
+        let defence = defence(0, 0);
+        let expired = defence.sign_form_token(super::unix_now() - 3601);
+        let (issued_at, nonce) = defence.verify_form_token(&expired).unwrap();
+        defence
+            .use_token(Some(super::FormToken {
+                issued_at,
+                nonce: nonce.to_owned(),
+            }))
+            .unwrap();
+        let token = defence.issue_form_token();
+        let checked = defence.check(&submission(Some(&token), None)).unwrap();
+        defence.use_token(checked).unwrap();
+
+        assert_eq!(defence.used_tokens.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let token = BotDefence::new(BotDefenceSettings {
+            form_secret: Secret::new("another-secret".into()),
+            ..defence(0, 0).settings
+        })
+        .issue_form_token();
+        assert_eq!(
+            defence(0, 0).check(&submission(Some(&token), None)),
+            Err(BotSignal::InvalidFormToken)
+        );
+    }
+
+    #[test]
+    fn forms_submitted_too_quickly_are_rejected() {
+        let defence = defence(5, 0);
+        let token = defence.issue_form_token();
+        assert_eq!(
+            defence.check(&submission(Some(&token), None)),
+            Err(BotSignal::TooFast)
+        );
+    }
+
+    #[test]
+    fn expired_tokens_are_rejected() {
+        let defence = defence(0, 0);
+        let token = defence.sign_form_token(super::unix_now() - 3601);
+        assert_eq!(
+            defence.check(&submission(Some(&token), None)),
+            Err(BotSignal::InvalidFormToken)
+        );
+    }
+
+    #[test]
+    fn proof_of_work_is_verified() {
+        let defence = defence(0, 8);
+        let token = defence.issue_form_token();
+        let nonce = (0u64..)
+            .map(|n| n.to_string())
+            .find(|n| verify_proof_of_work(&token, "ursula@example.com", n, 8))
+            .unwrap();
+
+        assert!(defence
+            .check(&submission(Some(&token), Some(&nonce)))
+            .is_ok());
+        assert_eq!(
+            defence.check(&submission(Some(&token), None)),
+            Err(BotSignal::ProofOfWork)
+        );
+    }
+}
@@ -1,56 +1,358 @@
+use std::path::{Path, PathBuf};
+
+use ipnet::IpNet;
 use secrecy::{ExposeSecret, Secret};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
+use url::Url;
 
-use crate::domain::SubscriberEmail;
+use crate::domain::{DomainPolicy, SubscriberEmail};
+use crate::email_client::EmailClient;
+use crate::migration::MigrationMode;
+use crate::rate_limit::Limit;
+use crate::routes::error_chain_format;
+use crate::telemetry::RedactionMode;
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub domain_policy: DomainPolicySettings,
+    pub rate_limit: RateLimitSettings,
+    pub bot_defence: BotDefenceSettings,
+    pub csrf: CsrfSettings,
+    pub health: HealthSettings,
+    pub metrics: MetricsSettings,
+    pub tracing: TracingSettings,
+    #[serde(default)]
+    pub secrets: SecretsSettings,
+    /// Serves read-only queries, which fall back to `database` while it is
+    /// unavailable.
+    pub read_replica: Option<DatabaseSettings>,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
-    pub base_url: String,
+    pub base_url: Url,
+    #[serde(default)]
+    pub run_migrations: MigrationMode,
+    /// How long in-flight requests and workers get to finish on shutdown.
+    pub shutdown_timeout_seconds: u64,
+    /// Reload when a file in `configuration/` changes, on top of SIGHUP.
+    #[serde(default)]
+    pub watch_configuration: bool,
+    /// Serve HTTPS instead of plain HTTP.
+    pub tls: Option<TlsSettings>,
+    /// Where requests come in; `host` and `port` only apply to `tcp`.
+    #[serde(default)]
+    pub listener: ListenerSettings,
+    pub http: HttpSettings,
+}
+
+/// The middleware every request goes through.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct HttpSettings {
+    /// Larger request bodies are refused with a 413. Subscriber imports
+    /// have their own limit.
+    pub max_body_bytes: usize,
+    /// Requests without a response by then get a 408. Subscriber imports
+    /// have their own timeout, newsletter deliveries are exempt.
+    pub request_timeout_milliseconds: u64,
+    /// Limit on the CSV streamed to `/admin/subscribers/import`.
+    pub max_import_bytes: usize,
+    pub import_timeout_milliseconds: u64,
+    pub compression: CompressionSettings,
+    pub cors: CorsSettings,
+    pub security_headers: SecurityHeadersSettings,
+}
+
+/// Encodings offered to clients that accept them.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct CompressionSettings {
+    pub gzip: bool,
+    pub br: bool,
+}
+
+/// Cross-origin access to the signup endpoints, for the embeddable widget.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct CorsSettings {
+    /// Origins such as `https://blog.example.com`; CORS is off when empty.
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    /// How long browsers may cache a preflight response.
+    pub max_age_seconds: u64,
+}
+
+/// Headers added to HTML responses; each one is left out when missing.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct SecurityHeadersSettings {
+    /// `Strict-Transport-Security` lifetime.
+    pub hsts_max_age_seconds: Option<u64>,
+    pub content_security_policy: Option<String>,
+    /// `X-Frame-Options`, `DENY` or `SAMEORIGIN`.
+    pub frame_options: Option<String>,
+    pub referrer_policy: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum ListenerSettings {
+    #[default]
+    Tcp,
+    /// A Unix domain socket, created at `path`. Its peers get `127.0.0.1`
+    /// as address, see `rate_limit.trust_unix_socket_peers`.
+    Unix {
+        path: PathBuf,
+        /// Octal permissions of the socket file, e.g. `"660"`.
+        mode: Option<String>,
+    },
+    /// The socket passed by systemd socket activation, see
+    /// `sd_listen_fds(3)`.
+    Systemd,
+}
+
+impl ListenerSettings {
+    pub fn unix_mode(&self) -> Result<Option<u32>, String> {
+        match self {
+            ListenerSettings::Unix {
+                mode: Some(mode), ..
+            } => match u32::from_str_radix(mode, 8) {
+                Ok(mode) if mode <= 0o777 => Ok(Some(mode)),
+                _ => Err(format!("`{mode}` is not an octal file mode such as `660`")),
+            },
+            _ => Ok(None),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct TlsSettings {
+    /// PEM files, read again whenever they change.
+    pub certificate_path: PathBuf,
+    pub private_key_path: PathBuf,
+    /// A plain HTTP port redirecting every request to `base_url`.
+    pub redirect_port: Option<u16>,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct DatabaseSettings {
     pub username: String,
+    #[serde(skip_serializing)]
     pub password: Secret<String>,
     pub port: u16,
     pub host: String,
     pub database_name: String,
     pub require_ssl: bool,
+    pub max_connections: u32,
+    /// How long a query waits for a free connection before failing.
+    pub acquire_timeout_milliseconds: u64,
+    /// Statements running for longer are cancelled by the server; no limit
+    /// when missing.
+    pub statement_timeout_milliseconds: Option<u64>,
 }
 
-#[derive(serde::Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct EmailClientSettings {
-    pub base_url: String,
-    pub sender_email: String,
+    pub base_url: Url,
+    pub sender_email: SubscriberEmail,
+    #[serde(skip_serializing)]
     pub authorization_token: Secret<String>,
     pub timeout_milliseconds: u64,
 }
 
-pub fn get_configuration() -> Result<Settings, config::ConfigError> {
-    let base_path = std::env::current_dir().expect("Failed to determine current dir.");
-    let configuration_dir = base_path.join("configuration");
+/// Email domains accepted on `/subscriptions`.
+///
+/// Domains on the `allow_list` bypass both the `deny_list` and the bundled
+/// list of disposable providers.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct DomainPolicySettings {
+    pub block_disposable: bool,
+    #[serde(default)]
+    pub allow_list: Vec<String>,
+    /// Extended by the domains blocked through the admin API, which cannot
+    /// remove these.
+    #[serde(default)]
+    pub deny_list: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct RateLimitSettings {
+    pub store: RateLimitStoreKind,
+    /// Peers whose `X-Forwarded-For` header is trusted to carry the client IP.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpNet>,
+    /// Trust the `X-Forwarded-For` header of every peer of a Unix socket
+    /// listener. Any local process can connect to it, so only set it when
+    /// the socket permissions leave it to the reverse proxy.
+    #[serde(default)]
+    pub trust_unix_socket_peers: bool,
+    pub per_ip: LimitSettings,
+    pub per_email: LimitSettings,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitStoreKind {
+    Memory,
+    Postgres,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct LimitSettings {
+    pub max_requests: u32,
+    pub window_seconds: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct BotDefenceSettings {
+    /// Key used to sign the timestamps embedded in signup forms.
+    #[serde(skip_serializing)]
+    pub form_secret: Secret<String>,
+    pub require_form_token: bool,
+    pub min_fill_seconds: u64,
+    pub max_form_age_seconds: u64,
+    /// Leading zero bits required from the proof of work, `0` disables it.
+    pub proof_of_work_bits: u8,
+}
+
+/// Cross-site request forgery checks on requests a browser could send from
+/// a form on another site.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct CsrfSettings {
+    /// Key used to sign the tokens of admin forms.
+    #[serde(skip_serializing)]
+    pub secret: Secret<String>,
+    pub admin_mode: CsrfMode,
+    pub token_ttl_seconds: u64,
+    /// Sites allowed to post the signup form, on top of `base_url` and the
+    /// CORS `allowed_origins`.
+    #[serde(default)]
+    pub trusted_origins: Vec<String>,
+}
+
+/// How admin forms prove they were served by us.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CsrfMode {
+    /// A token bound to the admin account, sent back in a form field or the
+    /// `X-CSRF-Token` header.
+    Synchronizer,
+    /// The same token must also come back in the `csrf_token` cookie.
+    DoubleSubmit,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct HealthSettings {
+    /// Budget for each dependency checked by `/health/ready`.
+    pub timeout_milliseconds: u64,
+    /// Also call the email provider, which catches rejected credentials.
+    pub check_email_provider: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct MetricsSettings {
+    /// Serve `/metrics` on this port instead of the application port,
+    /// to keep it off the public listener. On the application port it is
+    /// only served to admins.
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// The address the metrics port listens on.
+    pub host: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct TracingSettings {
+    /// Export spans to this OpenTelemetry collector, e.g.
+    /// `http://localhost:4317`. Nothing is exported when unset.
+    #[serde(default)]
+    pub otlp_endpoint: Option<String>,
+    #[serde(default)]
+    pub otlp_protocol: OtlpProtocol,
+    /// Reported as `service.name` on every exported span.
+    pub service_name: String,
+    /// Which spans and events are logged, in `RUST_LOG` syntax. `RUST_LOG`
+    /// takes precedence at startup.
+    pub log_filter: String,
+    pub redaction: RedactionSettings,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct RedactionSettings {
+    #[serde(default)]
+    pub mode: RedactionMode,
+    /// Key of the hashes logged in place of personal data in `hashed` mode.
+    #[serde(skip_serializing)]
+    pub salt: Secret<String>,
+}
+
+/// Where `${<provider>:<path>#<key>}` references in secret settings are
+/// looked up, see [`crate::secrets`].
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct SecretsSettings {
+    #[serde(default)]
+    pub vault: Option<VaultSettings>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct VaultSettings {
+    pub address: String,
+    #[serde(skip_serializing)]
+    pub token: Secret<String>,
+    /// Mount point of the KV version 2 secrets engine.
+    pub mount: String,
+    pub timeout_milliseconds: u64,
+}
+
+impl VaultSettings {
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OtlpProtocol {
+    #[default]
+    Grpc,
+    /// Protobuf over HTTP; spans are posted to `{otlp_endpoint}/v1/traces`.
+    Http,
+}
+
+/// Load the settings for `APP_ENVIRONMENT` from [`configuration_directory`]
+/// and validate them.
+///
+/// Every invalid setting is reported at once, along with where its value
+/// came from.
+pub fn get_configuration() -> Result<Settings, ConfigurationError> {
+    load_configuration(&configuration_directory())
+}
 
+/// `APP_CONFIG_DIR` if set, `configuration/` in the working directory
+/// otherwise.
+pub fn configuration_directory() -> PathBuf {
+    match std::env::var_os("APP_CONFIG_DIR") {
+        Some(directory) => PathBuf::from(directory),
+        None => std::env::current_dir()
+            .expect("Failed to determine current dir.")
+            .join("configuration"),
+    }
+}
+
+/// Load the settings for `APP_ENVIRONMENT` from `directory`.
+///
+/// Later sources take precedence: `base.yaml`, then `{env}.yaml`, then the
+/// optional, git-ignored `{env}.local.yaml`, then `APP_` environment
+/// variables.
+pub fn load_configuration(directory: &Path) -> Result<Settings, ConfigurationError> {
     let environment: Environment = std::env::var("APP_ENVIRONMENT")
         .unwrap_or_else(|_| "local".into())
         .try_into()
-        .expect("Failed to parse APP_ENVIRONMENT");
-    let environment_filename = format!("{}.yaml", environment.as_str());
+        .map_err(ConfigurationError::Environment)?;
 
-    let settings = config::Config::builder()
-        .add_source(config::File::from(configuration_dir.join("base.yaml")))
-        .add_source(config::File::from(
-            configuration_dir.join(environment_filename),
-        ))
+    let config = layered_files(directory, &environment)
         .add_source(
             config::Environment::with_prefix("APP")
                 .prefix_separator("_")
@@ -58,20 +360,483 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
         )
         .build()?;
 
-    settings.try_deserialize::<Settings>()
+    validated(config)
 }
 
-pub enum Environment {
-    Local,
-    Production,
+fn layered_files(
+    directory: &Path,
+    environment: &Environment,
+) -> config::ConfigBuilder<config::builder::DefaultState> {
+    let environment = environment.as_str();
+    config::Config::builder()
+        .add_source(config::File::from(directory.join("base.yaml")))
+        .add_source(config::File::from(
+            directory.join(format!("{environment}.yaml")),
+        ))
+        .add_source(
+            config::File::from(directory.join(format!("{environment}.local.yaml"))).required(false),
+        )
 }
 
-impl Environment {
-    pub fn as_str(&self) -> &'static str {
-        match self {
-            Environment::Local => "local",
-            Environment::Production => "production",
+fn validated(mut config: config::Config) -> Result<Settings, ConfigurationError> {
+    let mut invalid = Vec::new();
+    read_file_settings(&mut config.cache, "", &mut invalid);
+    parse_typed_settings(&mut config, &mut invalid);
+    let settings = match config.clone().try_deserialize::<Settings>() {
+        Ok(settings) => settings,
+        Err(e) => {
+            // A required setting is likely missing because its file is,
+            // which is reported already.
+            let missing_field =
+                matches!(&e, config::ConfigError::Message(m) if m.starts_with("missing field"));
+            if invalid.is_empty() || !missing_field {
+                invalid.push(undeserialisable_setting(&config, e));
+            }
+            return Err(ConfigurationError::Invalid(invalid));
+        }
+    };
+    invalid.extend(
+        settings
+            .validate()
+            .into_iter()
+            .map(|(key, message)| InvalidSetting {
+                key: key.to_owned(),
+                message,
+                source: value_source(&config, key),
+            }),
+    );
+    if invalid.is_empty() {
+        Ok(settings)
+    } else {
+        Err(ConfigurationError::Invalid(invalid))
+    }
+}
+
+type SettingParser = fn(&str) -> Result<(), String>;
+
+/// Settings deserialised into types that check their value, with the
+/// parser that checks it first and a valid stand-in for invalid values.
+const TYPED_SETTINGS: [(&str, SettingParser, &str); 3] = [
+    (
+        "application.base_url",
+        application_base_url,
+        "http://127.0.0.1",
+    ),
+    (
+        "email_client.base_url",
+        |value| http_url(value).map(|_| ()),
+        "http://127.0.0.1",
+    ),
+    (
+        "email_client.sender_email",
+        |value| match SubscriberEmail::parse(value.to_owned()) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(format!("`{value}` is not a valid email address")),
+        },
+        "sender@example.com",
+    ),
+];
+
+/// Check the settings listed in [`TYPED_SETTINGS`].
+///
+/// Serde would stop at the first invalid one, without saying which it is.
+/// Invalid values are swapped for their stand-in instead, so that the other
+/// settings are deserialised and checked too; the configuration is refused
+/// either way.
+fn parse_typed_settings(config: &mut config::Config, invalid: &mut Vec<InvalidSetting>) {
+    for (key, parse, stand_in) in TYPED_SETTINGS {
+        // Missing settings and other types are left to serde.
+        let Ok(value) = config.get_string(key) else {
+            continue;
+        };
+        if let Err(message) = parse(&value) {
+            invalid.push(InvalidSetting {
+                key: key.to_owned(),
+                message,
+                source: value_source(config, key),
+            });
+            replace_value(&mut config.cache, key, stand_in);
+        }
+    }
+}
+
+fn replace_value(mut value: &mut config::Value, key: &str, replacement: &str) {
+    for segment in key.split('.') {
+        let config::ValueKind::Table(table) = &mut value.kind else {
+            return;
+        };
+        let Some(child) = table.get_mut(segment) else {
+            return;
+        };
+        value = child;
+    }
+    value.kind = config::ValueKind::String(replacement.to_owned());
+}
+
+fn application_base_url(value: &str) -> Result<(), String> {
+    http_url(value)?;
+    // Links are built by appending paths to it.
+    if value.ends_with('/') {
+        Err("must not end with a `/`".into())
+    } else {
+        Ok(())
+    }
+}
+
+const FILE_SUFFIX: &str = "_file";
+
+/// Replace every `{key}_file` setting with `{key}`, set to the content of
+/// that file.
+///
+/// This is how secrets mounted as files are read, e.g. with
+/// `APP_DATABASE__PASSWORD_FILE=/run/secrets/database-password`; the
+/// file takes precedence over a `{key}` set anywhere else.
+fn read_file_settings(value: &mut config::Value, path: &str, invalid: &mut Vec<InvalidSetting>) {
+    let config::ValueKind::Table(table) = &mut value.kind else {
+        return;
+    };
+    let file_keys: Vec<_> = table
+        .keys()
+        .filter(|key| key.ends_with(FILE_SUFFIX))
+        .cloned()
+        .collect();
+    for file_key in file_keys {
+        let setting = table.remove(&file_key).expect("The key was just listed");
+        let full_key = join_key(path, &file_key);
+        let file = match &setting.kind {
+            config::ValueKind::Nil => continue,
+            config::ValueKind::String(file) => file.clone(),
+            _ => {
+                invalid.push(InvalidSetting {
+                    message: "must be the path of a file".into(),
+                    source: origin_source(&full_key, Some(&setting)),
+                    key: full_key,
+                });
+                continue;
+            }
+        };
+        match std::fs::read_to_string(&file) {
+            Ok(content) => {
+                let key = file_key.strip_suffix(FILE_SUFFIX).unwrap_or_default();
+                let content = content.trim_end_matches(['\r', '\n']).to_owned();
+                table.insert(key.to_owned(), config::Value::new(Some(&file), content));
+            }
+            Err(e) => invalid.push(InvalidSetting {
+                message: format!("cannot read `{file}`: {e}"),
+                source: origin_source(&full_key, Some(&setting)),
+                key: full_key,
+            }),
+        }
+    }
+    for (key, child) in table.iter_mut() {
+        read_file_settings(child, &join_key(path, key), invalid);
+    }
+}
+
+fn join_key(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_owned()
+    } else {
+        format!("{path}.{key}")
+    }
+}
+
+/// Serde stops at the first setting it cannot deserialise; only type
+/// errors say which one it is.
+fn undeserialisable_setting(config: &config::Config, e: config::ConfigError) -> InvalidSetting {
+    match e {
+        config::ConfigError::Type {
+            unexpected,
+            expected,
+            key: Some(key),
+            ..
+        } => InvalidSetting {
+            message: format!("invalid type: {unexpected}, expected {expected}"),
+            source: value_source(config, &key),
+            key,
+        },
+        e => InvalidSetting {
+            key: String::new(),
+            message: e.to_string(),
+            source: String::new(),
+        },
+    }
+}
+
+/// The file or environment variable `key` was read from.
+fn value_source(config: &config::Config, key: &str) -> String {
+    // Deserialising a `Value` loses its origin, walk the tree instead.
+    let mut value = Some(config.cache.clone());
+    for segment in key.split('.') {
+        value = value.and_then(|v| v.into_table().ok()?.remove(segment));
+    }
+    origin_source(key, value.as_ref())
+}
+
+fn origin_source(key: &str, value: Option<&config::Value>) -> String {
+    match value.and_then(config::Value::origin) {
+        Some("the environment") => format!("APP_{}", key.to_uppercase().replace('.', "__")),
+        Some(file) => file.to_owned(),
+        None => "default value".into(),
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum ConfigurationError {
+    #[error("Failed to load the configuration.")]
+    Load(#[from] config::ConfigError),
+    #[error("Invalid APP_ENVIRONMENT: {0}")]
+    Environment(String),
+    #[error("Invalid configuration:{}", format_invalid(.0))]
+    Invalid(Vec<InvalidSetting>),
+}
+
+impl std::fmt::Debug for ConfigurationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_format(self, f)
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidSetting {
+    /// Empty when the problem could not be tied to a setting.
+    pub key: String,
+    pub message: String,
+    /// See [`value_source`].
+    pub source: String,
+}
+
+fn format_invalid(invalid: &[InvalidSetting]) -> String {
+    invalid
+        .iter()
+        .map(|i| match i.key.as_str() {
+            "" => format!("\n  {}", i.message),
+            key => format!("\n  {} ({}): {}", key, i.source, i.message),
+        })
+        .collect()
+}
+
+impl Settings {
+    /// Check what deserialisation cannot, returning the key and problem
+    /// of every invalid setting.
+    fn validate(&self) -> Vec<(&'static str, String)> {
+        let mut invalid = Vec::new();
+        let mut check = |key, outcome: Result<(), String>| {
+            if let Err(message) = outcome {
+                invalid.push((key, message));
+            }
+        };
+
+        check(
+            "application.shutdown_timeout_seconds",
+            in_range(self.application.shutdown_timeout_seconds, 1, 3600),
+        );
+        check("database.port", in_range(self.database.port, 1, u16::MAX));
+        check(
+            "database.max_connections",
+            in_range(self.database.max_connections, 1, 1000),
+        );
+        check(
+            "database.acquire_timeout_milliseconds",
+            in_range(self.database.acquire_timeout_milliseconds, 1, 60_000),
+        );
+        if let Some(timeout) = self.database.statement_timeout_milliseconds {
+            check(
+                "database.statement_timeout_milliseconds",
+                in_range(timeout, 1, 86_400_000),
+            );
+        }
+        if let Some(replica) = &self.read_replica {
+            check("read_replica.port", in_range(replica.port, 1, u16::MAX));
+            check(
+                "read_replica.max_connections",
+                in_range(replica.max_connections, 1, 1000),
+            );
+            check(
+                "read_replica.acquire_timeout_milliseconds",
+                in_range(replica.acquire_timeout_milliseconds, 1, 60_000),
+            );
+            if let Some(timeout) = replica.statement_timeout_milliseconds {
+                check(
+                    "read_replica.statement_timeout_milliseconds",
+                    in_range(timeout, 1, 86_400_000),
+                );
+            }
+        }
+        check(
+            "email_client.timeout_milliseconds",
+            in_range(self.email_client.timeout_milliseconds, 1, 60_000),
+        );
+        check(
+            "rate_limit.per_ip.window_seconds",
+            in_range(self.rate_limit.per_ip.window_seconds, 1, 86_400),
+        );
+        check(
+            "rate_limit.per_email.window_seconds",
+            in_range(self.rate_limit.per_email.window_seconds, 1, 86_400),
+        );
+        check(
+            "health.timeout_milliseconds",
+            in_range(self.health.timeout_milliseconds, 1, 60_000),
+        );
+        check(
+            "application.listener.mode",
+            self.application.listener.unix_mode().map(|_| ()),
+        );
+        if let Some(tls) = &self.application.tls {
+            check(
+                "application.tls.redirect_port",
+                match tls.redirect_port {
+                    Some(port) if port != 0 && port == self.application.port => {
+                        Err("must differ from `application.port`".into())
+                    }
+                    _ => Ok(()),
+                },
+            );
+            if tls.redirect_port.is_some() {
+                // Requests are redirected to it.
+                check(
+                    "application.base_url",
+                    match self.application.base_url.scheme() == "https" {
+                        true => Ok(()),
+                        false => Err("must be an https URL to redirect to".into()),
+                    },
+                );
+            }
+        }
+        let http = &self.application.http;
+        check(
+            "application.http.max_body_bytes",
+            in_range(http.max_body_bytes, 1, 1 << 30),
+        );
+        check(
+            "application.http.request_timeout_milliseconds",
+            in_range(http.request_timeout_milliseconds, 1, 3_600_000),
+        );
+        check(
+            "application.http.max_import_bytes",
+            in_range(http.max_import_bytes, 1, 1 << 31),
+        );
+        check(
+            "application.http.import_timeout_milliseconds",
+            in_range(http.import_timeout_milliseconds, 1, 3_600_000),
+        );
+        for origin in &http.cors.allowed_origins {
+            check("application.http.cors.allowed_origins", cors_origin(origin));
+        }
+        check(
+            "application.http.cors.max_age_seconds",
+            in_range(http.cors.max_age_seconds, 0, 86_400),
+        );
+        check(
+            "csrf.token_ttl_seconds",
+            in_range(self.csrf.token_ttl_seconds, 1, 86_400),
+        );
+        for origin in &self.csrf.trusted_origins {
+            check("csrf.trusted_origins", cors_origin(origin));
+        }
+        let headers = &http.security_headers;
+        for (key, value) in [
+            (
+                "application.http.security_headers.content_security_policy",
+                &headers.content_security_policy,
+            ),
+            (
+                "application.http.security_headers.referrer_policy",
+                &headers.referrer_policy,
+            ),
+        ] {
+            if let Some(value) = value {
+                check(key, header_value(value));
+            }
+        }
+        if let Some(frame_options) = &headers.frame_options {
+            check(
+                "application.http.security_headers.frame_options",
+                match frame_options.as_str() {
+                    "DENY" | "SAMEORIGIN" => Ok(()),
+                    _ => Err(format!(
+                        "`{frame_options}` is neither `DENY` nor `SAMEORIGIN`"
+                    )),
+                },
+            );
+        }
+        check(
+            "metrics.port",
+            match self.metrics.port {
+                Some(port) if port != 0 && port == self.application.port => {
+                    Err("must differ from `application.port`".into())
+                }
+                _ => Ok(()),
+            },
+        );
+        check(
+            "tracing.log_filter",
+            tracing_subscriber::EnvFilter::try_new(&self.tracing.log_filter)
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+        );
+        if let Some(endpoint) = &self.tracing.otlp_endpoint {
+            check("tracing.otlp_endpoint", http_url(endpoint).map(|_| ()));
+        }
+        if let Some(vault) = &self.secrets.vault {
+            check(
+                "secrets.vault.address",
+                http_url(&vault.address).map(|_| ()),
+            );
+            check(
+                "secrets.vault.timeout_milliseconds",
+                in_range(vault.timeout_milliseconds, 1, 60_000),
+            );
         }
+        invalid
+    }
+}
+
+fn http_url(value: &str) -> Result<Url, String> {
+    let url = Url::parse(value).map_err(|e| format!("`{value}` is not a valid URL: {e}"))?;
+    match url.scheme() {
+        "http" | "https" => Ok(url),
+        scheme => Err(format!(
+            "`{scheme}` URLs are not supported, use http or https"
+        )),
+    }
+}
+
+/// Browsers send the origin as scheme, host and port only.
+fn cors_origin(value: &str) -> Result<(), String> {
+    let url = http_url(value)?;
+    if url.origin().ascii_serialization() == value {
+        Ok(())
+    } else {
+        Err(format!(
+            "`{value}` is not an origin such as `https://blog.example.com`"
+        ))
+    }
+}
+
+fn header_value(value: &str) -> Result<(), String> {
+    match value.is_empty() || !value.bytes().all(|b| b == b' ' || b.is_ascii_graphic()) {
+        true => Err(format!("`{value}` is not a valid header value")),
+        false => Ok(()),
+    }
+}
+
+fn in_range<T: PartialOrd + std::fmt::Display>(value: T, min: T, max: T) -> Result<(), String> {
+    if value < min || value > max {
+        Err(format!("{value} is not between {min} and {max}"))
+    } else {
+        Ok(())
+    }
+}
+
+/// The name of a deployment environment, e.g. `local`, `staging` or `ci`,
+/// whose settings are in `{name}.yaml`.
+pub struct Environment(String);
+
+impl Environment {
+    pub fn as_str(&self) -> &str {
+        &self.0
     }
 }
 
@@ -79,25 +844,86 @@ impl TryFrom<String> for Environment {
     type Error = String;
 
     fn try_from(value: String) -> Result<Self, Self::Error> {
-        match value.to_lowercase().as_str() {
-            "local" => Ok(Self::Local),
-            "production" => Ok(Self::Production),
-            other => Err(format!(
-                "{} is not a supported environment. \
-                Use either `local` or `production`.",
-                other
-            )),
+        let name = value.to_lowercase();
+        let valid_characters = name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if name.is_empty() || !valid_characters {
+            Err(format!(
+                "`{}` is not a valid environment name. \
+                Use letters, digits, `-` and `_` only.",
+                value
+            ))
+        } else if name == "base" {
+            Err("`base` holds the settings shared by every environment.".into())
+        } else {
+            Ok(Self(name))
         }
     }
 }
 
+impl ApplicationSettings {
+    pub fn shutdown_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.shutdown_timeout_seconds)
+    }
+}
+
+impl ApplicationSettings {
+    /// `base_url` as links are built from it: `Url` ends an empty path with
+    /// a `/`.
+    pub fn link_base(&self) -> &str {
+        self.base_url.as_str().trim_end_matches('/')
+    }
+}
+
+impl HttpSettings {
+    pub fn request_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.request_timeout_milliseconds)
+    }
+
+    pub fn import_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.import_timeout_milliseconds)
+    }
+}
+
+impl HealthSettings {
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
 impl EmailClientSettings {
     pub fn timeout(&self) -> std::time::Duration {
         std::time::Duration::from_millis(self.timeout_milliseconds)
     }
 
-    pub fn sender(&self) -> Result<SubscriberEmail, String> {
-        SubscriberEmail::parse(self.sender_email.clone())
+    pub fn client(self) -> EmailClient {
+        let timeout = self.timeout();
+        EmailClient::new(
+            self.base_url,
+            self.sender_email,
+            self.authorization_token,
+            timeout,
+        )
+    }
+}
+
+impl DomainPolicySettings {
+    pub fn policy(&self) -> DomainPolicy {
+        DomainPolicy::new(
+            self.allow_list.clone(),
+            self.deny_list.clone(),
+            self.block_disposable,
+        )
+    }
+}
+
+impl LimitSettings {
+    pub fn limit(&self) -> Limit {
+        Limit {
+            max_requests: self.max_requests,
+            window: std::time::Duration::from_secs(self.window_seconds),
+        }
     }
 }
 
@@ -120,4 +946,266 @@ impl DatabaseSettings {
     pub fn with_db(&self) -> PgConnectOptions {
         self.without_db().database(&self.database_name)
     }
+
+    /// Options for the connections of the application's pool, which are
+    /// subject to the statement timeout, unlike migrations.
+    pub fn pooled(&self) -> PgConnectOptions {
+        let options = self.with_db();
+        match self.statement_timeout_milliseconds {
+            Some(timeout) => options.options([("statement_timeout", timeout.to_string())]),
+            None => options,
+        }
+    }
+
+    pub fn acquire_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.acquire_timeout_milliseconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn load(env: &[(&str, &str)]) -> Result<Settings, ConfigurationError> {
+        load_from(Path::new("configuration"), "local", env)
+    }
+
+    fn load_from(
+        directory: &Path,
+        environment: &str,
+        env: &[(&str, &str)],
+    ) -> Result<Settings, ConfigurationError> {
+        let env: HashMap<_, _> = env
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let environment = Environment::try_from(environment.to_string()).unwrap();
+        let config = layered_files(directory, &environment)
+            .add_source(
+                config::Environment::with_prefix("APP")
+                    .prefix_separator("_")
+                    .separator("__")
+                    .source(Some(env)),
+            )
+            .build()?;
+        validated(config)
+    }
+
+    fn invalid(env: &[(&str, &str)]) -> Vec<InvalidSetting> {
+        match load(env) {
+            Err(ConfigurationError::Invalid(invalid)) => invalid,
+            other => panic!("Expected invalid settings, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn the_local_configuration_is_valid() {
+        assert!(load(&[]).is_ok());
+    }
+
+    #[test]
+    fn every_invalid_setting_is_reported_with_its_environment_variable() {
+        let invalid = invalid(&[
+            ("APP_APPLICATION__BASE_URL", "127.0.0.1"),
+            ("APP_EMAIL_CLIENT__SENDER_EMAIL", "not-an-email"),
+            ("APP_EMAIL_CLIENT__TIMEOUT_MILLISECONDS", "0"),
+        ]);
+
+        let reported: Vec<_> = invalid
+            .iter()
+            .map(|i| (i.key.as_str(), i.source.as_str()))
+            .collect();
+        assert_eq!(
+            reported,
+            [
+                ("application.base_url", "APP_APPLICATION__BASE_URL"),
+                (
+                    "email_client.sender_email",
+                    "APP_EMAIL_CLIENT__SENDER_EMAIL"
+                ),
+                (
+                    "email_client.timeout_milliseconds",
+                    "APP_EMAIL_CLIENT__TIMEOUT_MILLISECONDS"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn settings_of_the_wrong_type_are_reported_with_file_settings() {
+        let invalid = invalid(&[
+            ("APP_DATABASE__PASSWORD_FILE", "/does/not/exist"),
+            ("APP_APPLICATION__PORT", "abc"),
+        ]);
+
+        let reported: Vec<_> = invalid
+            .iter()
+            .map(|i| (i.key.as_str(), i.source.as_str()))
+            .collect();
+        assert_eq!(
+            reported,
+            [
+                ("database.password_file", "APP_DATABASE__PASSWORD_FILE"),
+                ("application.port", "APP_APPLICATION__PORT"),
+            ]
+        );
+        assert!(invalid[1].message.contains("abc"));
+    }
+
+    #[test]
+    fn urls_must_be_http_and_base_urls_must_not_end_with_a_slash() {
+        let invalid = invalid(&[
+            ("APP_APPLICATION__BASE_URL", "http://127.0.0.1/"),
+            ("APP_EMAIL_CLIENT__BASE_URL", "ftp://localhost"),
+        ]);
+        assert_eq!(invalid.len(), 2);
+        assert!(invalid[1].message.contains("ftp"));
+    }
+
+    #[test]
+    fn http_limits_and_security_headers_are_checked() {
+        let invalid = invalid(&[
+            ("APP_APPLICATION__HTTP__MAX_BODY_BYTES", "0"),
+            (
+                "APP_APPLICATION__HTTP__SECURITY_HEADERS__FRAME_OPTIONS",
+                "ALLOWALL",
+            ),
+        ]);
+
+        let keys: Vec<_> = invalid.iter().map(|i| i.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            [
+                "application.http.max_body_bytes",
+                "application.http.security_headers.frame_options"
+            ]
+        );
+    }
+
+    #[test]
+    fn cors_origins_are_bare_origins() {
+        assert!(cors_origin("https://blog.example.com").is_ok());
+        assert!(cors_origin("http://localhost:3000").is_ok());
+        assert!(cors_origin("https://blog.example.com/").is_err());
+        assert!(cors_origin("https://blog.example.com/widget").is_err());
+        assert!(cors_origin("blog.example.com").is_err());
+    }
+
+    #[test]
+    fn file_settings_are_replaced_by_the_content_of_the_file() {
+        let file = std::env::temp_dir().join(format!("zero2prod-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&file, "postmark-token\n").unwrap();
+
+        let settings = load(&[(
+            "APP_EMAIL_CLIENT__AUTHORIZATION_TOKEN_FILE",
+            file.to_str().unwrap(),
+        )])
+        .unwrap();
+
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(
+            settings.email_client.authorization_token.expose_secret(),
+            "postmark-token"
+        );
+    }
+
+    #[test]
+    fn unreadable_file_settings_are_reported() {
+        let invalid = invalid(&[("APP_DATABASE__PASSWORD_FILE", "/does/not/exist")]);
+
+        assert_eq!(invalid.len(), 1);
+        assert_eq!(invalid[0].key, "database.password_file");
+        assert_eq!(invalid[0].source, "APP_DATABASE__PASSWORD_FILE");
+        assert!(invalid[0].message.contains("/does/not/exist"));
+    }
+
+    #[test]
+    fn values_from_files_are_reported_with_their_path() {
+        let config = config::Config::builder()
+            .add_source(config::File::from(std::path::Path::new(
+                "configuration/base.yaml",
+            )))
+            .add_source(config::File::from(std::path::Path::new(
+                "configuration/local.yaml",
+            )))
+            .build()
+            .unwrap();
+        assert_eq!(
+            value_source(&config, "application.port"),
+            "configuration/base.yaml"
+        );
+        assert_eq!(
+            value_source(&config, "application.base_url"),
+            "configuration/local.yaml"
+        );
+    }
+
+    #[test]
+    fn any_environment_with_a_file_can_be_loaded_and_overridden_locally() {
+        let directory = std::env::temp_dir().join(format!("zero2prod-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&directory).unwrap();
+        std::fs::copy("configuration/base.yaml", directory.join("base.yaml")).unwrap();
+        std::fs::write(
+            directory.join("staging.yaml"),
+            "application:\n  host: 0.0.0.0\n  base_url: \"https://staging.example.com\"\n\
+             database:\n  require_ssl: true\n",
+        )
+        .unwrap();
+
+        let staging = load_from(&directory, "staging", &[]).unwrap();
+        assert_eq!(staging.application.host, "0.0.0.0");
+        assert_eq!(
+            staging.application.link_base(),
+            "https://staging.example.com"
+        );
+
+        std::fs::write(
+            directory.join("staging.local.yaml"),
+            "application:\n  base_url: \"ftp://staging.example.com\"\n",
+        )
+        .unwrap();
+        let invalid = match load_from(&directory, "staging", &[]) {
+            Err(ConfigurationError::Invalid(invalid)) => invalid,
+            other => panic!("Expected invalid settings, got {:?}", other.map(|_| ())),
+        };
+        let missing = load_from(&directory, "ci", &[]);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(invalid[0].key, "application.base_url");
+        assert!(invalid[0].source.ends_with("staging.local.yaml"));
+        assert!(matches!(missing, Err(ConfigurationError::Load(_))));
+    }
+
+    #[test]
+    fn environment_names_must_be_usable_as_file_names() {
+        assert_eq!(
+            Environment::try_from("Staging".to_string())
+                .unwrap()
+                .as_str(),
+            "staging"
+        );
+        assert!(Environment::try_from("ci-2".to_string()).is_ok());
+        assert!(Environment::try_from("../secrets".to_string()).is_err());
+        assert!(Environment::try_from("".to_string()).is_err());
+        assert!(Environment::try_from("base".to_string()).is_err());
+    }
+
+    #[test]
+    fn unix_socket_modes_must_be_octal_permissions() {
+        let settings = load(&[
+            ("APP_APPLICATION__LISTENER__KIND", "unix"),
+            ("APP_APPLICATION__LISTENER__PATH", "/run/zero2prod.sock"),
+            ("APP_APPLICATION__LISTENER__MODE", "660"),
+        ])
+        .unwrap();
+        assert_eq!(settings.application.listener.unix_mode(), Ok(Some(0o660)));
+
+        let invalid = invalid(&[
+            ("APP_APPLICATION__LISTENER__KIND", "unix"),
+            ("APP_APPLICATION__LISTENER__PATH", "/run/zero2prod.sock"),
+            ("APP_APPLICATION__LISTENER__MODE", "rw-rw----"),
+        ]);
+        assert_eq!(invalid[0].key, "application.listener.mode");
+    }
 }
@@ -0,0 +1,410 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::body::Bytes;
+use axum::extract::rejection::BytesRejection;
+use axum::extract::{FromRequest, Request, State};
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use secrecy::ExposeSecret;
+use sha2::Sha256;
+use url::Url;
+
+use crate::authentication::basic_authentication;
+use crate::configuration::{CsrfMode, CsrfSettings};
+use crate::problem_details::ProblemDetails;
+use crate::routes::error_chain_format;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub static X_CSRF_TOKEN: HeaderName = HeaderName::from_static("x-csrf-token");
+
+/// Name of both the form field and, in double-submit mode, the cookie
+/// carrying the token.
+pub const CSRF_TOKEN: &str = "csrf_token";
+
+/// Protection against forms on other sites posting to ours.
+///
+/// The public signup form is checked against the `Origin` (or `Referer`)
+/// the browser reports. Admin requests carry Basic credentials that the
+/// browser would replay on a forged post, so they also need a token from
+/// `/admin/csrf-token`, signed for the admin it was issued to.
+pub struct Csrf {
+    settings: CsrfSettings,
+    trusted_origins: Vec<String>,
+    secure_cookie: bool,
+}
+
+impl Csrf {
+    /// `base_url` and `cors_origins` are trusted on top of the configured
+    /// `trusted_origins`.
+    pub fn new(settings: CsrfSettings, base_url: &str, cors_origins: &[String]) -> Self {
+        let base_url = Url::parse(base_url).ok();
+        let trusted_origins = base_url
+            .iter()
+            .map(|url| url.origin().ascii_serialization())
+            .chain(cors_origins.iter().cloned())
+            .chain(settings.trusted_origins.iter().cloned())
+            .collect();
+        Self {
+            settings,
+            trusted_origins,
+            secure_cookie: base_url.is_some_and(|url| url.scheme() == "https"),
+        }
+    }
+
+    pub fn mode(&self) -> CsrfMode {
+        self.settings.admin_mode
+    }
+
+    fn is_trusted(&self, origin: &str) -> bool {
+        self.trusted_origins.iter().any(|trusted| trusted == origin)
+    }
+
+    pub fn issue_token(&self, username: &str) -> String {
+        self.sign_token(username, unix_now())
+    }
+
+    fn sign_token(&self, username: &str, issued_at: u64) -> String {
+        let nonce: String = std::iter::repeat_with(|| thread_rng().sample(Alphanumeric))
+            .map(char::from)
+            .take(16)
+            .collect();
+        let payload = format!("{}.{}", issued_at, nonce);
+        let signature =
+            URL_SAFE_NO_PAD.encode(self.mac(username, &payload).finalize().into_bytes());
+        format!("{}.{}", payload, signature)
+    }
+
+    fn mac(&self, username: &str, payload: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(self.settings.secret.expose_secret().as_bytes())
+            .expect("HMAC can take a key of any size");
+        mac.update(username.as_bytes());
+        mac.update(b":");
+        mac.update(payload.as_bytes());
+        mac
+    }
+
+    /// Whether `token` was issued to `username` and has not expired.
+    fn verify_token(&self, token: &str, username: &str) -> bool {
+        let Some((payload, signature)) = token.rsplit_once('.') else {
+            return false;
+        };
+        let Ok(signature) = URL_SAFE_NO_PAD.decode(signature) else {
+            return false;
+        };
+        if self
+            .mac(username, payload)
+            .verify_slice(&signature)
+            .is_err()
+        {
+            return false;
+        }
+        payload
+            .split_once('.')
+            .and_then(|(issued_at, _nonce)| issued_at.parse::<u64>().ok())
+            .is_some_and(|issued_at| {
+                unix_now().saturating_sub(issued_at) <= self.settings.token_ttl_seconds
+            })
+    }
+
+    /// The `Set-Cookie` value handing `token` to the browser for
+    /// double-submit mode.
+    pub fn cookie(&self, token: &str) -> HeaderValue {
+        let mut cookie = format!(
+            "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Strict",
+            CSRF_TOKEN, token, self.settings.token_ttl_seconds
+        );
+        if self.secure_cookie {
+            cookie.push_str("; Secure");
+        }
+        HeaderValue::from_str(&cookie).expect("Tokens are made of URL-safe characters")
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum CsrfError {
+    #[error("Requests from {0} are not accepted.")]
+    UntrustedOrigin(String),
+    #[error("A CSRF token is required, get one from /admin/csrf-token.")]
+    MissingToken,
+    #[error("The CSRF token is invalid or has expired.")]
+    InvalidToken,
+    #[error("The CSRF token does not match the `csrf_token` cookie.")]
+    CookieMismatch,
+    #[error(transparent)]
+    InvalidBody(#[from] BytesRejection),
+}
+
+impl std::fmt::Debug for CsrfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_format(self, f)
+    }
+}
+
+impl IntoResponse for CsrfError {
+    fn into_response(self) -> Response {
+        match self {
+            CsrfError::InvalidBody(rejection) => rejection.into_response(),
+            _ => {
+                tracing::warn!(reason = %self, "Refused a possibly forged request");
+                ProblemDetails::new(
+                    StatusCode::FORBIDDEN,
+                    "csrf-rejected",
+                    "Cross-Site Request Refused",
+                )
+                .with_detail(self.to_string())
+                .into_response()
+            }
+        }
+    }
+}
+
+/// Middleware refusing unsafe requests made from pages of untrusted origins.
+///
+/// Browsers report the origin of cross-site form posts in `Origin`, or at
+/// least `Referer`; requests with neither come from other clients and are
+/// let through.
+pub async fn check_origin(
+    State(csrf): State<Arc<Csrf>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, CsrfError> {
+    if !request.method().is_safe() {
+        if let Some(origin) = request_origin(request.headers()) {
+            if !csrf.is_trusted(&origin) {
+                return Err(CsrfError::UntrustedOrigin(origin));
+            }
+        }
+    }
+    Ok(next.run(request).await)
+}
+
+/// Middleware requiring a token from `/admin/csrf-token` on authenticated
+/// requests that another site could forge.
+///
+/// The token is read from the `X-CSRF-Token` header or the `csrf_token`
+/// field of a url-encoded form. Requests without credentials are left to
+/// the handler, which refuses them.
+pub async fn check_admin_token(
+    State(csrf): State<Arc<Csrf>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, CsrfError> {
+    if !is_simple_request(&request) {
+        return Ok(next.run(request).await);
+    }
+    let Ok(credentials) = basic_authentication(request.headers()) else {
+        return Ok(next.run(request).await);
+    };
+    let cookie = cookie(request.headers(), CSRF_TOKEN).map(ToOwned::to_owned);
+    let (token, request) = submitted_token(request).await?;
+    let token = token.ok_or(CsrfError::MissingToken)?;
+    if !csrf.verify_token(&token, &credentials.username) {
+        return Err(CsrfError::InvalidToken);
+    }
+    if csrf.mode() == CsrfMode::DoubleSubmit && cookie.as_deref() != Some(token.as_str()) {
+        return Err(CsrfError::CookieMismatch);
+    }
+    Ok(next.run(request).await)
+}
+
+/// Whether browsers let any page send the request without a CORS
+/// preflight: a POST of a form, of plain text or of nothing.
+fn is_simple_request(request: &Request) -> bool {
+    if request.method() != Method::POST {
+        return false;
+    }
+    match content_type(request.headers()) {
+        None => true,
+        Some(content_type) => matches!(
+            content_type.essence_str(),
+            "application/x-www-form-urlencoded" | "multipart/form-data" | "text/plain"
+        ),
+    }
+}
+
+fn content_type(headers: &HeaderMap) -> Option<mime::Mime> {
+    // An unparseable content type is as good as none.
+    headers
+        .get(header::CONTENT_TYPE)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+/// The origin of the page the request was made from, `null` when the
+/// browser hides it.
+fn request_origin(headers: &HeaderMap) -> Option<String> {
+    if let Some(origin) = headers.get(header::ORIGIN) {
+        return Some(origin.to_str().unwrap_or("null").to_owned());
+    }
+    let referer = headers.get(header::REFERER)?.to_str().ok()?;
+    Some(
+        Url::parse(referer)
+            .map(|url| url.origin().ascii_serialization())
+            .unwrap_or_else(|_| "null".into()),
+    )
+}
+
+fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find_map(|(key, value)| (key == name).then_some(value))
+}
+
+/// Take the token from the header, or from the form in the body, which is
+/// put back for the handler.
+async fn submitted_token(request: Request) -> Result<(Option<String>, Request), CsrfError> {
+    if let Some(token) = request.headers().get(&X_CSRF_TOKEN) {
+        return Ok((token.to_str().ok().map(ToOwned::to_owned), request));
+    }
+    let is_form = content_type(request.headers())
+        .is_some_and(|content_type| content_type == mime::APPLICATION_WWW_FORM_URLENCODED);
+    if !is_form {
+        return Ok((None, request));
+    }
+    let (parts, body) = request.into_parts();
+    // Bounded by the body limit the application applies to every form.
+    let body = Bytes::from_request(Request::from_parts(parts.clone(), body), &()).await?;
+    let token = url::form_urlencoded::parse(&body)
+        .find_map(|(key, value)| (key == CSRF_TOKEN).then(|| value.into_owned()));
+    Ok((token, Request::from_parts(parts, body.into())))
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::routing::post;
+    use axum::{middleware, Router};
+    use secrecy::Secret;
+    use tower_service::Service;
+
+    fn csrf(admin_mode: CsrfMode) -> Csrf {
+        Csrf::new(
+            CsrfSettings {
+                secret: Secret::new("secret".into()),
+                admin_mode,
+                token_ttl_seconds: 3600,
+                trusted_origins: vec!["https://www.example.com".into()],
+            },
+            "https://newsletter.example.com",
+            &["https://blog.example.com".into()],
+        )
+    }
+
+    /// `Basic ursula:password`
+    const CREDENTIALS: &str = "Basic dXJzdWxhOnBhc3N3b3Jk";
+
+    async fn post_form(csrf: Csrf, body: &str) -> (StatusCode, String) {
+        let mut app = Router::new()
+            .route("/admin/form", post(|body: String| async move { body }))
+            .layer(middleware::from_fn_with_state(
+                Arc::new(csrf),
+                check_admin_token,
+            ));
+        let request = Request::post("/admin/form")
+            .header(header::AUTHORIZATION, CREDENTIALS)
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body.to_owned()))
+            .unwrap();
+        let response = app.call(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn tokens_are_bound_to_the_admin_they_were_issued_to() {
+        let csrf = csrf(CsrfMode::Synchronizer);
+        let token = csrf.issue_token("ursula");
+
+        assert!(csrf.verify_token(&token, "ursula"));
+        assert!(!csrf.verify_token(&token, "octavia"));
+        assert!(!csrf.verify_token(&format!("{token}x"), "ursula"));
+    }
+
+    #[test]
+    fn expired_tokens_are_rejected() {
+        let csrf = csrf(CsrfMode::Synchronizer);
+        let token = csrf.sign_token("ursula", unix_now() - 3601);
+
+        assert!(!csrf.verify_token(&token, "ursula"));
+    }
+
+    #[test]
+    fn the_base_url_cors_and_configured_origins_are_trusted() {
+        let csrf = csrf(CsrfMode::Synchronizer);
+
+        assert!(csrf.is_trusted("https://newsletter.example.com"));
+        assert!(csrf.is_trusted("https://blog.example.com"));
+        assert!(csrf.is_trusted("https://www.example.com"));
+        assert!(!csrf.is_trusted("https://evil.example.com"));
+        assert!(!csrf.is_trusted("null"));
+    }
+
+    #[test]
+    fn the_referer_stands_in_for_a_missing_origin() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::REFERER,
+            HeaderValue::from_static("https://blog.example.com/posts/1?ref=x"),
+        );
+        assert_eq!(
+            request_origin(&headers).as_deref(),
+            Some("https://blog.example.com")
+        );
+
+        headers.insert(header::ORIGIN, HeaderValue::from_static("null"));
+        assert_eq!(request_origin(&headers).as_deref(), Some("null"));
+    }
+
+    #[tokio::test]
+    async fn a_token_in_the_form_is_accepted_and_the_form_passed_on() {
+        let csrf = csrf(CsrfMode::Synchronizer);
+        let body = format!("title=Hello&{}={}", CSRF_TOKEN, csrf.issue_token("ursula"));
+
+        let (status, received) = post_form(csrf, &body).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(received, body);
+    }
+
+    #[tokio::test]
+    async fn forms_without_a_token_are_refused() {
+        let (status, _) = post_form(csrf(CsrfMode::Synchronizer), "title=Hello").await;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn double_submit_requires_the_cookie_to_match() {
+        let csrf = csrf(CsrfMode::DoubleSubmit);
+        let body = format!("{}={}", CSRF_TOKEN, csrf.issue_token("ursula"));
+
+        let (status, _) = post_form(csrf, &body).await;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::HashSet;
+use std::sync::RwLock;
+
+use crate::domain::SubscriberEmail;
+
+const DISPOSABLE_DOMAINS: &str = include_str!("disposable_domains.txt");
+
+/// Why an otherwise well-formed email address was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DomainRejection {
+    Disposable,
+    Blocked,
+}
+
+impl DomainRejection {
+    /// Stable identifier returned to API clients.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            DomainRejection::Disposable => "disposable_domain",
+            DomainRejection::Blocked => "blocked_domain",
+        }
+    }
+}
+
+impl std::fmt::Display for DomainRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DomainRejection::Disposable => write!(f, "Disposable email domains are not accepted."),
+            DomainRejection::Blocked => write!(f, "This email domain is not accepted."),
+        }
+    }
+}
+
+/// Decides which email domains may subscribe.
+///
+/// A domain matches a list entry if it is equal to it or one of its
+/// subdomains. Entries on the allow list are never rejected. The deny list
+/// from the configuration can be extended at runtime through the admin
+/// API, which stores those domains in the `blocked_domains` table.
+pub struct DomainPolicy {
+    allow_list: HashSet<String>,
+    configured_deny_list: HashSet<String>,
+    /// The configured deny list and the blocked domains.
+    deny_list: RwLock<HashSet<String>>,
+    disposable: HashSet<String>,
+}
+
+impl DomainPolicy {
+    pub fn new(allow_list: Vec<String>, deny_list: Vec<String>, block_disposable: bool) -> Self {
+        let disposable = if block_disposable {
+            DISPOSABLE_DOMAINS
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                .map(normalise)
+                .collect()
+        } else {
+            HashSet::new()
+        };
+        let configured_deny_list: HashSet<String> =
+            deny_list.iter().map(|d| normalise(d)).collect();
+        Self {
+            allow_list: allow_list.iter().map(|d| normalise(d)).collect(),
+            deny_list: RwLock::new(configured_deny_list.clone()),
+            configured_deny_list,
+            disposable,
+        }
+    }
+
+    pub fn check(&self, email: &SubscriberEmail) -> Result<(), DomainRejection> {
+        let domain = normalise(email.domain());
+        if matches(&self.allow_list, &domain) {
+            return Ok(());
+        }
+        if matches(&self.deny_list.read().unwrap(), &domain) {
+            return Err(DomainRejection::Blocked);
+        }
+        if matches(&self.disposable, &domain) {
+            return Err(DomainRejection::Disposable);
+        }
+        Ok(())
+    }
+
+    /// The current deny list, sorted alphabetically.
+    pub fn denied_domains(&self) -> Vec<String> {
+        let mut domains: Vec<String> = self.deny_list.read().unwrap().iter().cloned().collect();
+        domains.sort();
+        domains
+    }
+
+    /// Replace the domains denied on top of the configured ones.
+    pub fn set_blocked(&self, blocked: &[String]) {
+        let mut deny_list = self.configured_deny_list.clone();
+        deny_list.extend(blocked.iter().map(|d| normalise(d)));
+        *self.deny_list.write().unwrap() = deny_list;
+    }
+
+    /// Whether the domain is on the deny list of the configuration, which
+    /// the admin API cannot remove it from.
+    pub fn is_configured(&self, domain: &str) -> bool {
+        self.configured_deny_list.contains(&normalise(domain))
+    }
+
+    /// Returns `false` if the domain was already denied.
+    pub fn deny(&self, domain: &str) -> bool {
+        self.deny_list.write().unwrap().insert(normalise(domain))
+    }
+
+    /// Returns `false` if the domain was not on the deny list. Configured
+    /// domains stay on it.
+    pub fn undeny(&self, domain: &str) -> bool {
+        !self.is_configured(domain) && self.deny_list.write().unwrap().remove(&normalise(domain))
+    }
+}
+
+/// The form domains are compared and stored in.
+pub fn normalise(domain: &str) -> String {
+    domain.trim().trim_end_matches('.').to_lowercase()
+}
+
+fn matches(list: &HashSet<String>, domain: &str) -> bool {
+    let mut candidate = domain;
+    loop {
+        if list.contains(candidate) {
+            return true;
+        }
+        match candidate.split_once('.') {
+            Some((_, parent)) => candidate = parent,
+            None => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DomainPolicy, DomainRejection};
+    use crate::domain::SubscriberEmail;
+
+    fn email(s: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(s.to_string()).unwrap()
+    }
+
+    #[test]
+    fn bundled_disposable_domains_are_rejected() {
+        let policy = DomainPolicy::new(vec![], vec![], true);
+        assert_eq!(
+            policy.check(&email("bot@mailinator.com")),
+            Err(DomainRejection::Disposable)
+        );
+    }
+
+    #[test]
+    fn disposable_domains_are_accepted_when_blocking_is_disabled() {
+        let policy = DomainPolicy::new(vec![], vec![], false);
+        assert!(policy.check(&email("bot@mailinator.com")).is_ok());
+    }
+
+    #[test]
+    fn subdomains_of_a_denied_domain_are_rejected() {
+        let policy = DomainPolicy::new(vec![], vec!["example.com".into()], true);
+        assert_eq!(
+            policy.check(&email("ursula@mail.EXAMPLE.com")),
+            Err(DomainRejection::Blocked)
+        );
+        assert!(policy.check(&email("ursula@notexample.com")).is_ok());
+    }
+
+    #[test]
+    fn the_allow_list_takes_precedence() {
+        let policy =
+            DomainPolicy::new(vec!["yopmail.com".into()], vec!["yopmail.com".into()], true);
+        assert!(policy.check(&email("ursula@yopmail.com")).is_ok());
+    }
+
+    #[test]
+    fn the_deny_list_can_be_edited_at_runtime() {
+        let policy = DomainPolicy::new(vec![], vec![], true);
+        assert!(policy.deny("Spam.io"));
+        assert!(!policy.deny("spam.io"));
+        assert_eq!(
+            policy.check(&email("ursula@spam.io")),
+            Err(DomainRejection::Blocked)
+        );
+        assert!(policy.undeny("spam.io"));
+        assert!(policy.check(&email("ursula@spam.io")).is_ok());
+    }
+
+    #[test]
+    fn configured_domains_stay_denied() {
+        let policy = DomainPolicy::new(vec![], vec!["example.com".into()], true);
+        policy.set_blocked(&["spam.io".into()]);
+        policy.set_blocked(&[]);
+        assert!(!policy.undeny("example.com"));
+        assert_eq!(policy.denied_domains(), ["example.com"]);
+    }
+}
@@ -1,7 +1,9 @@
+mod domain_policy;
 mod new_subscriber;
 mod subscriber_email;
 mod subscriber_name;
 
+pub use domain_policy::{normalise as normalise_domain, DomainPolicy, DomainRejection};
 pub use new_subscriber::NewSubscriber;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
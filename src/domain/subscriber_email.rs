@@ -1,6 +1,9 @@
 use validator::ValidateEmail;
 
-#[derive(Clone)]
+use crate::telemetry::Redacted;
+
+#[derive(Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(try_from = "String")]
 pub struct SubscriberEmail(String);
 
 impl SubscriberEmail {
@@ -8,9 +11,25 @@ impl SubscriberEmail {
         if ValidateEmail::validate_email(&s) {
             Ok(Self(s))
         } else {
-            Err(format!("{} is not a valid subscriber email.", s))
+            Err(format!("{} is not a valid subscriber email.", Redacted(&s)))
         }
     }
+
+    pub fn domain(&self) -> &str {
+        // `parse` guarantees there is an `@` in the address.
+        self.0
+            .rsplit_once('@')
+            .map(|(_, domain)| domain)
+            .unwrap_or_default()
+    }
+}
+
+impl TryFrom<String> for SubscriberEmail {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse(value)
+    }
 }
 
 impl AsRef<str> for SubscriberEmail {
@@ -21,9 +40,15 @@ impl AsRef<str> for SubscriberEmail {
 
 impl std::fmt::Display for SubscriberEmail {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        // We just forward to the Display implementation of
-        // the wrapped String.
-        self.0.fmt(f)
+        // Displayed addresses end up in logs and error messages, use
+        // `as_ref` to get the actual address.
+        Redacted(&self.0).fmt(f)
+    }
+}
+
+impl std::fmt::Debug for SubscriberEmail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SubscriberEmail({})", Redacted(&self.0))
     }
 }
 
@@ -62,6 +87,18 @@ mod tests {
         assert!(SubscriberEmail::parse(email).is_err());
     }
 
+    #[test]
+    fn addresses_are_redacted_when_displayed() {
+        let email = SubscriberEmail::parse("ursula_le_guin@gmail.com".to_string()).unwrap();
+        assert_eq!(email.as_ref(), "ursula_le_guin@gmail.com");
+        assert!(!email.to_string().contains("ursula_le_guin"));
+
+        let error = SubscriberEmail::parse("ursula_le_guingmail.com".to_string())
+            .err()
+            .unwrap();
+        assert!(!error.contains("ursula_le_guin"));
+    }
+
     #[quickcheck_macros::quickcheck]
     fn valid_emails_are_parsed_successfully(valid_email: ValidEmailFixture) -> bool {
         SubscriberEmail::parse(valid_email.0).is_ok()
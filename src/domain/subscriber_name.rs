@@ -1,5 +1,7 @@
 use unicode_segmentation::UnicodeSegmentation;
 
+use crate::telemetry::Redacted;
+
 pub struct SubscriberName(String);
 
 impl SubscriberName {
@@ -11,7 +13,7 @@ impl SubscriberName {
         let containst_forbidden_chars = s.chars().any(|g| forbiddent_chars.contains(&g));
 
         if is_empty_or_whitespace || is_too_long || containst_forbidden_chars {
-            Err(format!("{} is not a valid subscriber name.", s))
+            Err(format!("{} is not a valid subscriber name.", Redacted(&s)))
         } else {
             Ok(Self(s))
         }
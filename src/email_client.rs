@@ -1,42 +1,79 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+use reqwest::header::{HeaderMap, HeaderValue};
 use reqwest::Client;
 use secrecy::{ExposeSecret, Secret};
 use serde::Serialize;
+use url::Url;
 
 use crate::domain::SubscriberEmail;
+use crate::request_id::{RequestId, X_REQUEST_ID};
+use crate::telemetry::inject_trace_context;
+
+const PROVIDER: &str = "postmark";
 
 #[derive(Clone)]
 pub struct EmailClient {
     http_client: Client,
-    base_url: String,
+    base_url: Url,
     sender: SubscriberEmail,
     authorization_token: Secret<String>,
+    /// Shared by every clone, see [`EmailClient::set_timeout`].
+    timeout: Arc<ArcSwap<Duration>>,
 }
 
 impl EmailClient {
     pub fn new(
-        base_url: String,
+        base_url: Url,
         sender: SubscriberEmail,
         authorization_token: Secret<String>,
-        timeout: std::time::Duration,
+        timeout: Duration,
     ) -> Self {
-        let http_client = Client::builder().timeout(timeout).build().unwrap();
         Self {
-            http_client,
+            http_client: Client::new(),
             base_url,
             sender,
             authorization_token,
+            timeout: Arc::new(ArcSwap::from_pointee(timeout)),
         }
     }
 
+    /// Change the timeout of the requests sent from now on, by this client
+    /// and all its clones.
+    pub fn set_timeout(&self, timeout: Duration) {
+        self.timeout.store(Arc::new(timeout));
+    }
+
+    /// `template` identifies the kind of email in metrics, e.g. `confirmation`.
     pub async fn send_email(
+        &self,
+        template: &'static str,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), reqwest::Error> {
+        let outcome = self
+            .post_email(recipient, subject, html_content, text_content)
+            .await;
+        let labels = [("provider", PROVIDER), ("template", template)];
+        match outcome {
+            Ok(()) => metrics::counter!("emails_sent_total", &labels).increment(1),
+            Err(_) => metrics::counter!("emails_failed_total", &labels).increment(1),
+        }
+        outcome
+    }
+
+    async fn post_email(
         &self,
         recipient: &SubscriberEmail,
         subject: &str,
         html_content: &str,
         text_content: &str,
     ) -> Result<(), reqwest::Error> {
-        // TODO: change the url type
-        let url = format!("{}/email", self.base_url);
+        let url = self.endpoint("email");
         let request_body = SendEmailRequest {
             from: self.sender.as_ref(),
             to: recipient.as_ref(),
@@ -46,7 +83,9 @@ impl EmailClient {
         };
         let _builder = self
             .http_client
-            .post(&url)
+            .post(url)
+            .timeout(**self.timeout.load())
+            .headers(correlation_headers())
             .header(
                 "X-Postmark-Server-Token",
                 self.authorization_token.expose_secret(),
@@ -57,6 +96,47 @@ impl EmailClient {
             .error_for_status()?;
         Ok(())
     }
+
+    /// `segment` appended to the base URL, keeping any path it has:
+    /// `Url::join` would replace its last segment.
+    fn endpoint(&self, segment: &str) -> Url {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .expect("The email client base URL cannot be a base.")
+            .pop_if_empty()
+            .push(segment);
+        url
+    }
+
+    /// Check that the provider is reachable and accepts our credentials.
+    pub async fn ping(&self) -> Result<(), reqwest::Error> {
+        let url = self.endpoint("server");
+        self.http_client
+            .get(url)
+            .timeout(**self.timeout.load())
+            .headers(correlation_headers())
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Lets the provider's side of a delivery be matched with the request and
+/// the trace that triggered it.
+fn correlation_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    inject_trace_context(&mut headers);
+    if let Some(request_id) = RequestId::current() {
+        if let Ok(value) = HeaderValue::from_str(request_id.as_str()) {
+            headers.insert(X_REQUEST_ID.clone(), value);
+        }
+    }
+    headers
 }
 
 #[derive(Serialize)]
@@ -115,7 +195,7 @@ mod tests {
 
     fn email_client(base_url: String) -> EmailClient {
         EmailClient::new(
-            base_url,
+            base_url.parse().unwrap(),
             email(),
             Secret::new(Faker.fake()),
             std::time::Duration::from_millis(200),
@@ -139,8 +219,26 @@ mod tests {
 
         // Act
         let _ = email_client
-            .send_email(&email(), &subject(), &content(), &content())
+            .send_email("test", &email(), &subject(), &content(), &content())
+            .await;
+    }
+
+    #[tokio::test]
+    async fn send_email_keeps_the_path_of_the_base_url() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(format!("{}/postmark", mock_server.uri()));
+        Mock::given(path("/postmark/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email("test", &email(), &subject(), &content(), &content())
             .await;
+
+        assert!(outcome.is_ok());
     }
 
     #[tokio::test]
@@ -156,7 +254,7 @@ mod tests {
             .await;
 
         let outcome = email_client
-            .send_email(&email(), &subject(), &content(), &content())
+            .send_email("test", &email(), &subject(), &content(), &content())
             .await;
 
         assert!(outcome.is_ok());
@@ -176,7 +274,7 @@ mod tests {
             .await;
         // Act
         let outcome = email_client
-            .send_email(&email(), &subject(), &content(), &content())
+            .send_email("test", &email(), &subject(), &content(), &content())
             .await;
 
         assert!(outcome.is_err())
@@ -196,9 +294,25 @@ mod tests {
             .await;
 
         let outcome = email_client
-            .send_email(&email(), &subject(), &content(), &content())
+            .send_email("test", &email(), &subject(), &content(), &content())
             .await;
 
         assert!(outcome.is_err());
     }
+
+    #[tokio::test]
+    async fn ping_fails_if_the_credentials_are_rejected() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(path("/server"))
+            .and(method("GET"))
+            .respond_with(ResponseTemplate::new(401))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        assert!(email_client.ping().await.is_err());
+    }
 }
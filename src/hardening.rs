@@ -0,0 +1,170 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::{Request, State};
+use axum::http::header::{self, InvalidHeaderValue};
+use axum::http::{HeaderName, HeaderValue, Method};
+use axum::middleware::Next;
+use axum::response::Response;
+use tower_http::compression::CompressionLayer;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::configuration::{CompressionSettings, CorsSettings, SecurityHeadersSettings};
+
+/// Headers added to HTML responses by [`add_security_headers`].
+#[derive(Debug)]
+pub struct SecurityHeaders(Vec<(HeaderName, HeaderValue)>);
+
+impl SecurityHeaders {
+    pub fn new(settings: &SecurityHeadersSettings) -> Result<Self, InvalidHeaderValue> {
+        let mut headers = Vec::new();
+        if let Some(max_age) = settings.hsts_max_age_seconds {
+            headers.push((
+                header::STRICT_TRANSPORT_SECURITY,
+                HeaderValue::from_str(&format!("max-age={max_age}"))?,
+            ));
+        }
+        for (name, value) in [
+            (
+                header::CONTENT_SECURITY_POLICY,
+                &settings.content_security_policy,
+            ),
+            (header::X_FRAME_OPTIONS, &settings.frame_options),
+            (header::REFERRER_POLICY, &settings.referrer_policy),
+        ] {
+            if let Some(value) = value {
+                headers.push((name, HeaderValue::from_str(value)?));
+            }
+        }
+        Ok(Self(headers))
+    }
+}
+
+/// Middleware adding the security headers to HTML responses, leaving alone
+/// those a handler has set itself.
+pub async fn add_security_headers(
+    State(headers): State<Arc<SecurityHeaders>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+    if is_html(&response) {
+        for (name, value) in &headers.0 {
+            response
+                .headers_mut()
+                .entry(name)
+                .or_insert_with(|| value.clone());
+        }
+    }
+    response
+}
+
+fn is_html(response: &Response) -> bool {
+    response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<mime::Mime>().ok())
+        .is_some_and(|content_type| content_type.essence_str() == mime::TEXT_HTML.as_ref())
+}
+
+/// Lets the allowed origins call the signup endpoints from a browser; `None`
+/// when there are none.
+pub fn cors(settings: &CorsSettings) -> Result<Option<CorsLayer>, InvalidHeaderValue> {
+    if settings.allowed_origins.is_empty() {
+        return Ok(None);
+    }
+    let origins = settings
+        .allowed_origins
+        .iter()
+        .map(|origin| HeaderValue::from_str(origin))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Some(
+        CorsLayer::new()
+            .allow_origin(AllowOrigin::list(origins))
+            .allow_methods([Method::GET, Method::POST])
+            .allow_headers([header::CONTENT_TYPE])
+            .max_age(Duration::from_secs(settings.max_age_seconds)),
+    ))
+}
+
+/// Compresses responses with the enabled encodings; `None` when there are
+/// none.
+pub fn compression(settings: &CompressionSettings) -> Option<CompressionLayer> {
+    (settings.gzip || settings.br)
+        .then(|| CompressionLayer::new().gzip(settings.gzip).br(settings.br))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::response::{Html, IntoResponse};
+    use axum::routing::get;
+    use axum::{middleware, Json, Router};
+    use tower_service::Service;
+
+    fn settings() -> SecurityHeadersSettings {
+        SecurityHeadersSettings {
+            hsts_max_age_seconds: Some(600),
+            content_security_policy: Some("default-src 'self'".into()),
+            frame_options: Some("DENY".into()),
+            referrer_policy: None,
+        }
+    }
+
+    async fn headers_on(path: &str) -> axum::http::HeaderMap {
+        let headers = Arc::new(SecurityHeaders::new(&settings()).unwrap());
+        let mut app = Router::new()
+            .route("/page", get(|| async { Html("<p>Hello</p>") }))
+            .route("/data", get(|| async { Json("hello") }))
+            .route(
+                "/framed",
+                get(|| async {
+                    (
+                        [(header::X_FRAME_OPTIONS, "SAMEORIGIN")],
+                        Html("<p>Hello</p>"),
+                    )
+                        .into_response()
+                }),
+            )
+            .layer(middleware::from_fn_with_state(
+                headers,
+                add_security_headers,
+            ));
+        let request = Request::get(path).body(Body::empty()).unwrap();
+        app.call(request).await.unwrap().headers().clone()
+    }
+
+    #[tokio::test]
+    async fn html_responses_get_the_configured_headers() {
+        let headers = headers_on("/page").await;
+
+        assert_eq!(headers[header::STRICT_TRANSPORT_SECURITY], "max-age=600");
+        assert_eq!(
+            headers[header::CONTENT_SECURITY_POLICY],
+            "default-src 'self'"
+        );
+        assert_eq!(headers[header::X_FRAME_OPTIONS], "DENY");
+        assert!(headers.get(header::REFERRER_POLICY).is_none());
+    }
+
+    #[tokio::test]
+    async fn other_responses_are_left_alone() {
+        let headers = headers_on("/data").await;
+
+        assert!(headers.get(header::CONTENT_SECURITY_POLICY).is_none());
+        assert!(headers.get(header::STRICT_TRANSPORT_SECURITY).is_none());
+    }
+
+    #[tokio::test]
+    async fn headers_set_by_the_handler_win() {
+        let headers = headers_on("/framed").await;
+
+        assert_eq!(headers[header::X_FRAME_OPTIONS], "SAMEORIGIN");
+        assert_eq!(
+            headers[header::CONTENT_SECURITY_POLICY],
+            "default-src 'self'"
+        );
+    }
+}
@@ -1,6 +1,23 @@
+pub mod authentication;
+pub mod blocked_domains;
+pub mod bot_defence;
 pub mod configuration;
+pub mod csrf;
 pub mod domain;
 pub mod email_client;
+pub mod hardening;
+pub mod maintenance;
+pub mod migration;
+pub mod problem_details;
+pub mod prometheus;
+pub mod rate_limit;
+pub mod reload;
+pub mod request_id;
 pub mod routes;
+pub mod secrets;
+pub mod server;
+pub mod shutdown;
 pub mod startup;
+pub mod subscriber_import;
 pub mod telemetry;
+pub mod tls;
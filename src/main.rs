@@ -1,18 +1,43 @@
+use opentelemetry::trace::TracerProvider as _;
 use zero2prod::{
     configuration::get_configuration,
+    secrets::{configured_providers, resolve_secrets},
     startup::Application,
-    telemetry::{get_subscriber, init_subscriber},
+    telemetry::{get_subscriber, init_redaction, init_subscriber, tracer_provider},
 };
 
 #[tokio::main]
 async fn main() -> Result<(), std::io::Error> {
-    let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
+    let mut configuration = match get_configuration() {
+        Ok(configuration) => configuration,
+        Err(e) => {
+            eprintln!("{e:?}");
+            std::process::exit(1);
+        }
+    };
+    let providers = configured_providers(&configuration.secrets);
+    if let Err(e) = resolve_secrets(&mut configuration, &providers).await {
+        eprintln!("{e:?}");
+        std::process::exit(1);
+    }
+
+    let tracer_provider =
+        tracer_provider(&configuration.tracing).expect("Failed to set up trace export");
+    let subscriber = get_subscriber(
+        "zero2prod".into(),
+        configuration.tracing.log_filter.clone(),
+        std::io::stdout,
+        Some(tracer_provider.tracer("zero2prod")),
+    );
     init_subscriber(subscriber);
+    init_redaction(&configuration.tracing.redaction);
 
-    let configuration = get_configuration().expect("Failed to read config");
     let application = Application::build(configuration).await?;
 
     application.run_untill_stopped().await?;
 
+    // Flush the spans still waiting to be exported.
+    let _ = tokio::task::spawn_blocking(move || tracer_provider.shutdown()).await;
+
     Ok(())
 }
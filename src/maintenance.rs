@@ -0,0 +1,121 @@
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use sqlx::PgPool;
+
+use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::email_client::EmailClient;
+use crate::routes::{generate_subscriptions_token, send_confirmation_email, store_token};
+use crate::startup::ApplicationBaseUrl;
+
+#[derive(Debug, Default)]
+pub struct ResendReport {
+    pub sent: u64,
+    pub failed: u64,
+}
+
+/// Email a confirmation link again to every subscriber that has been
+/// pending for longer than `older_than`.
+///
+/// Subscribers keep their existing token, so links already sent stay valid.
+#[tracing::instrument(
+    name = "Resend pending confirmations",
+    skip(pool, email_client, base_url)
+)]
+pub async fn resend_pending_confirmations(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &ApplicationBaseUrl,
+    older_than: Duration,
+) -> Result<ResendReport, anyhow::Error> {
+    let pending = sqlx::query!(
+        r#"
+        SELECT s.id, s.email, s.name, t.subscription_token AS "subscription_token?"
+        FROM subscriptions s
+        LEFT JOIN LATERAL (
+            SELECT subscription_token FROM subscription_tokens
+            WHERE subscriber_id = s.id
+            LIMIT 1
+        ) t ON true
+        WHERE s.status = 'pending_confirmation' AND s.subscribed_at < $1
+        "#,
+        Utc::now() - older_than,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch pending subscribers.")?;
+
+    let mut report = ResendReport::default();
+    for row in pending {
+        let subscriber = match (
+            SubscriberEmail::parse(row.email),
+            SubscriberName::parse(row.name),
+        ) {
+            (Ok(email), Ok(name)) => NewSubscriber { email, name },
+            (Err(e), _) | (_, Err(e)) => {
+                tracing::warn!(subscriber_id = %row.id, "Skipping an invalid stored subscriber: {}", e);
+                report.failed += 1;
+                continue;
+            }
+        };
+        let token = match row.subscription_token {
+            Some(token) => token,
+            None => {
+                let token = generate_subscriptions_token();
+                let mut transaction = pool.begin().await?;
+                store_token(&mut transaction, row.id, &token)
+                    .await
+                    .context("Failed to store a new confirmation token.")?;
+                transaction.commit().await?;
+                token
+            }
+        };
+        match send_confirmation_email(email_client, subscriber, base_url.clone(), &token).await {
+            Ok(()) => report.sent += 1,
+            Err(e) => {
+                tracing::warn!(error.cause_chain = ?e, subscriber_id = %row.id, "Failed to resend a confirmation email");
+                report.failed += 1;
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Delete subscribers that never confirmed within `older_than`, together
+/// with their tokens. Returns how many subscribers were deleted.
+#[tracing::instrument(name = "Purge stale pending subscribers", skip(pool))]
+pub async fn purge_stale_pending(
+    pool: &PgPool,
+    older_than: Duration,
+) -> Result<u64, anyhow::Error> {
+    let cutoff = Utc::now() - older_than;
+    let mut transaction = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        DELETE FROM subscription_tokens
+        WHERE subscriber_id IN (
+            SELECT id FROM subscriptions
+            WHERE status = 'pending_confirmation' AND subscribed_at < $1
+        )
+        "#,
+        cutoff,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the tokens of stale subscribers.")?;
+    let deleted = sqlx::query!(
+        r#"
+        DELETE FROM subscriptions
+        WHERE status = 'pending_confirmation' AND subscribed_at < $1
+        "#,
+        cutoff,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete stale subscribers.")?
+    .rows_affected();
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the purge of stale subscribers.")?;
+    Ok(deleted)
+}
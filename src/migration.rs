@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use sqlx::migrate::{Migrate, MigrateError, Migrator};
+use sqlx::{Connection, PgConnection};
+
+use crate::configuration::DatabaseSettings;
+use crate::routes::error_chain_format;
+
+/// Arbitrary, but shared by every instance of the application.
+const MIGRATION_LOCK_ID: i64 = 0x7a65_726f_3270_726f;
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// What `Application::build` does about `migrations/` before serving.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MigrationMode {
+    /// Leave the schema alone, it is managed out of band.
+    #[default]
+    Off,
+    /// Apply pending migrations.
+    Apply,
+    /// Refuse to start if any migration is pending.
+    Check,
+}
+
+/// Bring the schema up to date, or verify that it is, according to `mode`.
+///
+/// Instances starting together take turns through a Postgres advisory
+/// lock, held on a dedicated connection so that it is released even if
+/// a migration fails halfway.
+#[tracing::instrument(name = "Migrate the database", skip(settings))]
+pub async fn migrate_database(
+    settings: &DatabaseSettings,
+    mode: MigrationMode,
+) -> Result<(), MigrationError> {
+    if mode == MigrationMode::Off {
+        return Ok(());
+    }
+    let mut connection = PgConnection::connect_with(&settings.with_db())
+        .await
+        .map_err(MigrationError::Connect)?;
+    sqlx::query("SELECT pg_advisory_lock($1)")
+        .bind(MIGRATION_LOCK_ID)
+        .execute(&mut connection)
+        .await
+        .map_err(MigrationError::Lock)?;
+
+    let outcome = match mode {
+        MigrationMode::Apply => MIGRATOR.run(&mut connection).await.map_err(Into::into),
+        MigrationMode::Check => verify_schema(&mut connection).await,
+        MigrationMode::Off => unreachable!(),
+    };
+
+    // Closing the connection releases the lock as well, this is only
+    // a courtesy to the instances waiting on it.
+    let _ = sqlx::query("SELECT pg_advisory_unlock($1)")
+        .bind(MIGRATION_LOCK_ID)
+        .execute(&mut connection)
+        .await;
+    let _ = connection.close().await;
+    outcome
+}
+
+/// Fails unless every migration under `migrations/` has been applied
+/// unchanged.
+///
+/// Only reads the schema: a database without the migrations table has
+/// every migration pending.
+pub async fn verify_schema(connection: &mut PgConnection) -> Result<(), MigrationError> {
+    let migrated: bool = sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+        .fetch_one(&mut *connection)
+        .await
+        .map_err(MigrateError::from)?;
+    let applied: HashMap<_, _> = if migrated {
+        if let Some(version) = connection.dirty_version().await? {
+            return Err(MigrateError::Dirty(version).into());
+        }
+        connection
+            .list_applied_migrations()
+            .await?
+            .into_iter()
+            .map(|m| (m.version, m.checksum))
+            .collect()
+    } else {
+        HashMap::new()
+    };
+
+    let mut pending = Vec::new();
+    for migration in MIGRATOR
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+    {
+        match applied.get(&migration.version) {
+            None => pending.push(migration.version),
+            Some(checksum) if *checksum != migration.checksum => {
+                return Err(MigrateError::VersionMismatch(migration.version).into())
+            }
+            Some(_) => {}
+        }
+    }
+    if pending.is_empty() {
+        Ok(())
+    } else {
+        Err(MigrationError::Pending(pending))
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum MigrationError {
+    #[error("Failed to connect to the database to migrate it.")]
+    Connect(#[source] sqlx::Error),
+    #[error("Failed to acquire the migration lock.")]
+    Lock(#[source] sqlx::Error),
+    #[error("The database schema is behind, pending migrations: {0:?}.")]
+    Pending(Vec<i64>),
+    #[error("Failed to migrate the database.")]
+    Migrate(#[from] MigrateError),
+}
+
+impl std::fmt::Debug for MigrationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_format(self, f)
+    }
+}
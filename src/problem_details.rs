@@ -0,0 +1,89 @@
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+
+use crate::request_id::RequestId;
+
+/// An RFC 7807 `application/problem+json` error body.
+///
+/// Only information that is safe to show to API consumers belongs here:
+/// cause chains of unexpected errors are logged, never returned.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    type_uri: String,
+    title: &'static str,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<FieldError>,
+}
+
+/// A problem with a single input field.
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl ProblemDetails {
+    /// `kind` is a short slug identifying the problem type, e.g. `rate-limited`.
+    pub fn new(status: StatusCode, kind: &str, title: &'static str) -> Self {
+        Self {
+            type_uri: format!("/problems/{}", kind),
+            title,
+            status: status.as_u16(),
+            detail: None,
+            request_id: None,
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn with_errors(mut self, errors: Vec<FieldError>) -> Self {
+        self.errors = errors;
+        self
+    }
+
+    /// A 500 that logs `error` with its cause chain and hides it from the client.
+    pub fn unexpected(error: &dyn std::fmt::Debug) -> Self {
+        tracing::error!(error.cause_chain = ?error, "Unexpected error while handling a request");
+        Self::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal-error",
+            "Internal Server Error",
+        )
+        .with_detail("An unexpected error occurred, please try again later.")
+    }
+
+    pub fn status(&self) -> StatusCode {
+        StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+}
+
+impl IntoResponse for ProblemDetails {
+    fn into_response(mut self) -> Response {
+        self.request_id = RequestId::current().map(|id| id.to_string());
+        let status = self.status();
+        let mut response = (status, Json(self)).into_response();
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/problem+json"),
+        );
+        response
+    }
+}
+
+/// Fallback for unknown routes.
+pub async fn not_found() -> ProblemDetails {
+    ProblemDetails::new(StatusCode::NOT_FOUND, "not-found", "Not Found")
+}
@@ -0,0 +1,155 @@
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::header;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use sqlx::PgPool;
+
+use crate::authentication::AdminUser;
+use crate::startup::ReadPool;
+
+const REQUEST_DURATION: &str = "http_request_duration_seconds";
+const DELIVERY_QUEUE_DEPTH: &str = "email_delivery_queue_depth";
+
+/// Renders the process-wide metrics in the Prometheus text format.
+///
+/// Gauges describing the state of the system, rather than events, are
+/// sampled when scraped.
+pub struct Metrics {
+    handle: PrometheusHandle,
+    pool: PgPool,
+    read_pool: ReadPool,
+}
+
+impl Metrics {
+    pub fn new(pool: PgPool, read_pool: ReadPool) -> Self {
+        Self {
+            handle: recorder().clone(),
+            pool,
+            read_pool,
+        }
+    }
+
+    async fn render(&self) -> String {
+        self.sample_pool();
+        if let Err(e) = self.sample_subscribers().await {
+            tracing::warn!(error.cause_chain = ?e, "Failed to count subscribers for metrics");
+        }
+        self.handle.render()
+    }
+
+    fn sample_pool(&self) {
+        let size = self.pool.size() as f64;
+        let idle = self.pool.num_idle() as f64;
+        metrics::gauge!("db_pool_connections", "state" => "idle").set(idle);
+        metrics::gauge!("db_pool_connections", "state" => "in_use").set(size - idle);
+        metrics::gauge!("db_pool_max_connections")
+            .set(self.pool.options().get_max_connections() as f64);
+    }
+
+    async fn sample_subscribers(&self) -> Result<(), anyhow::Error> {
+        let counts = tokio::time::timeout(Duration::from_secs(2), async {
+            let mut connection = self.read_pool.acquire().await?;
+            sqlx::query!(
+                r#"SELECT status, count(*) AS "count!" FROM subscriptions GROUP BY status"#
+            )
+            .fetch_all(&mut *connection)
+            .await
+        })
+        .await??;
+        for row in counts {
+            metrics::gauge!("subscribers", "status" => row.status).set(row.count as f64);
+        }
+        Ok(())
+    }
+}
+
+/// The global recorder; only one can be installed per process.
+fn recorder() -> &'static PrometheusHandle {
+    static RECORDER: OnceLock<PrometheusHandle> = OnceLock::new();
+    RECORDER.get_or_init(|| {
+        PrometheusBuilder::new()
+            .set_buckets_for_metric(
+                Matcher::Full(REQUEST_DURATION.to_string()),
+                &[
+                    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+                ],
+            )
+            .expect("Failed to configure histogram buckets")
+            .install_recorder()
+            .expect("Failed to install the Prometheus recorder")
+    })
+}
+
+pub async fn metrics_endpoint(State(metrics): State<Arc<Metrics>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics.render().await,
+    )
+}
+
+/// `/metrics` on the application port, which is public.
+pub async fn admin_metrics_endpoint(
+    _: AdminUser,
+    metrics: State<Arc<Metrics>>,
+) -> impl IntoResponse {
+    metrics_endpoint(metrics).await
+}
+
+/// Middleware counting requests and their latency by route and status.
+///
+/// Labels use the route template, e.g. `/admin/blocked_domains/:domain`,
+/// to keep their cardinality bounded.
+pub async fn track_http_metrics(request: Request, next: Next) -> Response {
+    let path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_owned())
+        .unwrap_or_else(|| "unmatched".into());
+    let method = request.method().to_string();
+    let started = Instant::now();
+
+    let response = next.run(request).await;
+
+    let labels = [
+        ("method", method),
+        ("path", path),
+        ("status", response.status().as_u16().to_string()),
+    ];
+    metrics::counter!("http_requests_total", &labels).increment(1);
+    metrics::histogram!(REQUEST_DURATION, &labels).record(started.elapsed().as_secs_f64());
+    response
+}
+
+/// Emails waiting to be handed to the provider, across every delivery
+/// in progress.
+///
+/// Whatever is still queued when this is dropped, e.g. because delivery
+/// failed halfway, is taken off the gauge.
+pub struct DeliveryQueue {
+    remaining: usize,
+}
+
+impl DeliveryQueue {
+    pub fn enqueue(emails: usize) -> Self {
+        metrics::gauge!(DELIVERY_QUEUE_DEPTH).increment(emails as f64);
+        Self { remaining: emails }
+    }
+
+    /// One email left the queue, whether it was sent or not.
+    pub fn dequeue(&mut self) {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            metrics::gauge!(DELIVERY_QUEUE_DEPTH).decrement(1.0);
+        }
+    }
+}
+
+impl Drop for DeliveryQueue {
+    fn drop(&mut self) {
+        metrics::gauge!(DELIVERY_QUEUE_DEPTH).decrement(self.remaining as f64);
+    }
+}
@@ -0,0 +1,453 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use arc_swap::ArcSwap;
+use async_trait::async_trait;
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use ipnet::IpNet;
+use sqlx::PgPool;
+
+use crate::configuration::{RateLimitSettings, RateLimitStoreKind};
+use crate::problem_details::ProblemDetails;
+use crate::shutdown::Shutdown;
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(300);
+/// The in-memory store drops windows that have run out past this size.
+const MAX_IN_MEMORY_WINDOWS: usize = 10_000;
+
+/// How many requests a single key may make within a fixed window.
+#[derive(Debug, Clone, Copy)]
+pub struct Limit {
+    pub max_requests: u32,
+    pub window: Duration,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Decision {
+    Allowed,
+    Limited { retry_after: Duration },
+}
+
+/// Counts hits per key.
+///
+/// The in-memory store is enough for a single instance; deployments running
+/// several replicas should share their counters through Postgres.
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+    async fn hit(&self, key: &str, limit: Limit) -> Result<Decision, anyhow::Error>;
+
+    /// Forget windows that started more than `max_window` ago.
+    async fn purge_expired(&self, max_window: Duration) -> Result<u64, anyhow::Error>;
+}
+
+#[derive(Default)]
+pub struct InMemoryStore {
+    windows: Mutex<HashMap<String, Window>>,
+}
+
+/// Keys are hit with different limits, each window remembers its length.
+struct Window {
+    started_at: Instant,
+    length: Duration,
+    hits: u32,
+}
+
+impl Window {
+    fn has_expired(&self, now: Instant) -> bool {
+        now.duration_since(self.started_at) >= self.length
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for InMemoryStore {
+    async fn hit(&self, key: &str, limit: Limit) -> Result<Decision, anyhow::Error> {
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap();
+        // Keep memory bounded by dropping windows that have run out.
+        if windows.len() > MAX_IN_MEMORY_WINDOWS {
+            windows.retain(|_, window| !window.has_expired(now));
+        }
+        let window = windows.entry(key.to_string()).or_insert(Window {
+            started_at: now,
+            length: limit.window,
+            hits: 0,
+        });
+        window.length = limit.window;
+        if window.has_expired(now) {
+            window.started_at = now;
+            window.hits = 0;
+        }
+        window.hits += 1;
+        Ok(decide(
+            window.hits,
+            limit,
+            now.duration_since(window.started_at),
+        ))
+    }
+
+    async fn purge_expired(&self, max_window: Duration) -> Result<u64, anyhow::Error> {
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap();
+        let before = windows.len();
+        windows.retain(|_, window| now.duration_since(window.started_at) < max_window);
+        Ok((before - windows.len()) as u64)
+    }
+}
+
+pub struct PostgresStore {
+    pool: PgPool,
+}
+
+impl PostgresStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for PostgresStore {
+    // Keys may hold email addresses, keep them out of the span.
+    #[tracing::instrument(name = "Record a rate limited hit", skip(self, key))]
+    async fn hit(&self, key: &str, limit: Limit) -> Result<Decision, anyhow::Error> {
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO rate_limits (key, window_started_at, hits)
+            VALUES ($1, now(), 1)
+            ON CONFLICT (key) DO UPDATE SET
+                hits = CASE
+                    WHEN rate_limits.window_started_at + make_interval(secs => $2) <= now() THEN 1
+                    ELSE rate_limits.hits + 1
+                END,
+                window_started_at = CASE
+                    WHEN rate_limits.window_started_at + make_interval(secs => $2) <= now() THEN now()
+                    ELSE rate_limits.window_started_at
+                END
+            RETURNING hits, EXTRACT(EPOCH FROM now() - window_started_at)::float8 AS "elapsed!"
+            "#,
+            key,
+            limit.window.as_secs_f64(),
+        )
+        .fetch_one(&self.pool)
+        .await
+        .context("Failed to record a hit in the rate limit table.")?;
+        let elapsed = Duration::from_secs_f64(row.elapsed.max(0.0));
+        Ok(decide(row.hits as u32, limit, elapsed))
+    }
+
+    async fn purge_expired(&self, max_window: Duration) -> Result<u64, anyhow::Error> {
+        let deleted = sqlx::query!(
+            "DELETE FROM rate_limits WHERE window_started_at + make_interval(secs => $1) <= now()",
+            max_window.as_secs_f64(),
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to purge expired rate limit windows.")?
+        .rows_affected();
+        Ok(deleted)
+    }
+}
+
+fn decide(hits: u32, limit: Limit, elapsed: Duration) -> Decision {
+    if hits <= limit.max_requests {
+        Decision::Allowed
+    } else {
+        Decision::Limited {
+            retry_after: limit.window.saturating_sub(elapsed),
+        }
+    }
+}
+
+/// Throttles public endpoints by client IP and by target email address.
+pub struct RateLimiter {
+    store: Arc<dyn RateLimitStore>,
+    limits: ArcSwap<Limits>,
+    trusted_proxies: Vec<IpNet>,
+    /// Every peer is a reverse proxy, see [`RateLimiter::new`].
+    peers_are_proxies: bool,
+}
+
+struct Limits {
+    per_ip: Limit,
+    per_email: Limit,
+}
+
+impl RateLimiter {
+    /// `peers_are_proxies` is set when listening on a Unix socket whose
+    /// peers are trusted: they have no address of their own, the reverse
+    /// proxy passes the client's on.
+    pub fn new(settings: &RateLimitSettings, pool: PgPool, peers_are_proxies: bool) -> Self {
+        let store: Arc<dyn RateLimitStore> = match settings.store {
+            RateLimitStoreKind::Memory => Arc::new(InMemoryStore::default()),
+            RateLimitStoreKind::Postgres => Arc::new(PostgresStore::new(pool)),
+        };
+        Self {
+            store,
+            limits: ArcSwap::from_pointee(Limits {
+                per_ip: settings.per_ip.limit(),
+                per_email: settings.per_email.limit(),
+            }),
+            trusted_proxies: settings.trusted_proxies.clone(),
+            peers_are_proxies,
+        }
+    }
+
+    /// Applies to the requests checked from now on.
+    pub fn set_limits(&self, per_ip: Limit, per_email: Limit) {
+        self.limits.store(Arc::new(Limits { per_ip, per_email }));
+    }
+
+    pub async fn check_ip(&self, ip: IpAddr) -> Result<(), RateLimited> {
+        let limit = self.limits.load().per_ip;
+        self.check(&format!("ip:{}", ip), limit).await
+    }
+
+    pub async fn check_email(&self, email: &str) -> Result<(), RateLimited> {
+        let limit = self.limits.load().per_email;
+        self.check(&format!("email:{}", email.to_lowercase()), limit)
+            .await
+    }
+
+    async fn check(&self, key: &str, limit: Limit) -> Result<(), RateLimited> {
+        match self.store.hit(key, limit).await {
+            Ok(Decision::Allowed) => Ok(()),
+            Ok(Decision::Limited { retry_after }) => Err(RateLimited { retry_after }),
+            Err(e) => {
+                // Fail open: an outage of the store should not take the
+                // signup flow down with it.
+                tracing::error!(error.cause_chain = ?e, "Failed to check the rate limit");
+                Ok(())
+            }
+        }
+    }
+
+    /// The address of the client that sent the request.
+    ///
+    /// `X-Forwarded-For` is only honoured when the peer is a trusted proxy;
+    /// it is walked from the right, skipping further trusted hops.
+    pub fn client_ip(&self, peer: SocketAddr, headers: &HeaderMap) -> IpAddr {
+        let peer_ip = peer.ip();
+        if !self.peers_are_proxies && !self.is_trusted(peer_ip) {
+            return peer_ip;
+        }
+        let forwarded = headers
+            .get_all("X-Forwarded-For")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .filter_map(|ip| ip.trim().parse::<IpAddr>().ok())
+            .collect::<Vec<_>>();
+        forwarded
+            .iter()
+            .rev()
+            .find(|ip| !self.is_trusted(**ip))
+            .or(forwarded.first())
+            .copied()
+            .unwrap_or(peer_ip)
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|net| net.contains(&ip))
+    }
+
+    async fn purge_expired(&self) -> Result<u64, anyhow::Error> {
+        let max_window = {
+            let limits = self.limits.load();
+            limits.per_ip.window.max(limits.per_email.window)
+        };
+        self.store.purge_expired(max_window).await
+    }
+}
+
+/// Background worker deleting expired windows from the store, until shutdown.
+pub async fn sweep_expired_windows(rate_limiter: Arc<RateLimiter>, shutdown: Shutdown) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = interval.tick() => match rate_limiter.purge_expired().await {
+                Ok(purged) => tracing::debug!(purged, "Purged expired rate limit windows"),
+                Err(e) => tracing::warn!(error.cause_chain = ?e, "Failed to purge expired rate limit windows"),
+            },
+        }
+    }
+    tracing::info!("Stopped the rate limit sweeper");
+}
+
+/// The client went over its limit and should retry after the given delay.
+#[derive(Debug)]
+pub struct RateLimited {
+    pub retry_after: Duration,
+}
+
+impl std::fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Too many requests, retry after {} seconds.",
+            self.retry_after_seconds()
+        )
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+impl RateLimited {
+    fn retry_after_seconds(&self) -> u64 {
+        // Round up, `Retry-After: 0` would invite an immediate retry.
+        self.retry_after.as_secs() + u64::from(self.retry_after.subsec_nanos() > 0)
+    }
+}
+
+impl IntoResponse for RateLimited {
+    fn into_response(self) -> Response {
+        let mut response = ProblemDetails::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            "rate-limited",
+            "Too Many Requests",
+        )
+        .with_detail(self.to_string())
+        .into_response();
+        response.headers_mut().insert(
+            header::RETRY_AFTER,
+            HeaderValue::from(self.retry_after_seconds()),
+        );
+        response
+    }
+}
+
+/// Middleware rejecting requests from clients that went over the per-IP limit.
+pub async fn limit_by_client_ip(
+    State(rate_limiter): State<Arc<RateLimiter>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let client_ip = rate_limiter.client_ip(peer, request.headers());
+    if let Err(limited) = rate_limiter.check_ip(client_ip).await {
+        tracing::warn!(%client_ip, "Rate limited a client");
+        return limited.into_response();
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Decision, InMemoryStore, Limit, Limits, RateLimitStore, RateLimiter};
+    use arc_swap::ArcSwap;
+    use axum::http::HeaderMap;
+    use std::net::{IpAddr, SocketAddr};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn limiter(trusted_proxies: &[&str]) -> RateLimiter {
+        limiter_behind(trusted_proxies, false)
+    }
+
+    fn limiter_behind(trusted_proxies: &[&str], peers_are_proxies: bool) -> RateLimiter {
+        let limit = Limit {
+            max_requests: 1,
+            window: Duration::from_secs(60),
+        };
+        RateLimiter {
+            store: Arc::new(InMemoryStore::default()),
+            limits: ArcSwap::from_pointee(Limits {
+                per_ip: limit,
+                per_email: limit,
+            }),
+            trusted_proxies: trusted_proxies.iter().map(|n| n.parse().unwrap()).collect(),
+            peers_are_proxies,
+        }
+    }
+
+    fn forwarded_for(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Forwarded-For", value.parse().unwrap());
+        headers
+    }
+
+    #[tokio::test]
+    async fn pruning_the_in_memory_store_keeps_longer_windows() {
+        let store = InMemoryStore::default();
+        let long = Limit {
+            max_requests: 1,
+            window: Duration::from_secs(3600),
+        };
+        let short = Limit {
+            max_requests: 1,
+            window: Duration::from_millis(1),
+        };
+        store.hit("email:ursula", long).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        // Going over the size prunes with the short windows' hits.
+        for i in 0..=super::MAX_IN_MEMORY_WINDOWS + 1 {
+            store.hit(&format!("ip:{i}"), short).await.unwrap();
+        }
+
+        assert!(store.windows.lock().unwrap().len() <= super::MAX_IN_MEMORY_WINDOWS);
+        assert!(matches!(
+            store.hit("email:ursula", long).await.unwrap(),
+            Decision::Limited { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn in_memory_store_limits_hits_within_a_window() {
+        let store = InMemoryStore::default();
+        let limit = Limit {
+            max_requests: 2,
+            window: Duration::from_secs(60),
+        };
+        assert_eq!(store.hit("key", limit).await.unwrap(), Decision::Allowed);
+        assert_eq!(store.hit("key", limit).await.unwrap(), Decision::Allowed);
+        assert!(matches!(
+            store.hit("key", limit).await.unwrap(),
+            Decision::Limited { .. }
+        ));
+        assert_eq!(store.hit("other", limit).await.unwrap(), Decision::Allowed);
+    }
+
+    #[tokio::test]
+    async fn in_memory_store_purges_expired_windows() {
+        let store = InMemoryStore::default();
+        let limit = Limit {
+            max_requests: 1,
+            window: Duration::from_secs(60),
+        };
+        store.hit("key", limit).await.unwrap();
+
+        assert_eq!(store.purge_expired(limit.window).await.unwrap(), 0);
+        assert_eq!(store.purge_expired(Duration::ZERO).await.unwrap(), 1);
+        assert_eq!(store.hit("key", limit).await.unwrap(), Decision::Allowed);
+    }
+
+    #[test]
+    fn forwarded_for_is_ignored_from_untrusted_peers() {
+        let limiter = limiter(&[]);
+        let peer: SocketAddr = "203.0.113.7:4000".parse().unwrap();
+        let ip = limiter.client_ip(peer, &forwarded_for("198.51.100.1"));
+        assert_eq!(ip, peer.ip());
+    }
+
+    #[test]
+    fn forwarded_for_is_honoured_from_trusted_proxies() {
+        let limiter = limiter(&["10.0.0.0/8"]);
+        let peer: SocketAddr = "10.0.0.2:4000".parse().unwrap();
+        let ip = limiter.client_ip(peer, &forwarded_for("192.0.2.9, 198.51.100.1, 10.0.0.3"));
+        assert_eq!(ip, "198.51.100.1".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn forwarded_for_is_honoured_from_unix_socket_peers() {
+        let limiter = limiter_behind(&["10.0.0.0/8"], true);
+        let peer: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let ip = limiter.client_ip(peer, &forwarded_for("198.51.100.1, 10.0.0.3"));
+        assert_eq!(ip, "198.51.100.1".parse::<IpAddr>().unwrap());
+    }
+}
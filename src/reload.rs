@@ -0,0 +1,248 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+use notify::{RecursiveMode, Watcher};
+use secrecy::ExposeSecret;
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+use crate::configuration::{configuration_directory, get_configuration, Settings};
+use crate::email_client::EmailClient;
+use crate::rate_limit::RateLimiter;
+use crate::secrets::{is_reference, secret_settings};
+use crate::shutdown::Shutdown;
+use crate::telemetry::set_log_filter;
+
+/// Editors write files in several steps, wait for them to settle.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Swaps settings that are safe to change into the running application.
+///
+/// Only the email client timeout, rate limits, log filter and health
+/// checks are reloaded. Every other setting, secrets included, keeps the
+/// value it had at startup; changes to them are refused with a warning.
+#[derive(Clone)]
+pub struct Reloader {
+    settings: Arc<ArcSwap<Settings>>,
+    email_client: EmailClient,
+    rate_limiter: Arc<RateLimiter>,
+}
+
+impl Reloader {
+    pub fn new(
+        settings: Arc<ArcSwap<Settings>>,
+        email_client: EmailClient,
+        rate_limiter: Arc<RateLimiter>,
+    ) -> Self {
+        Self {
+            settings,
+            email_client,
+            rate_limiter,
+        }
+    }
+
+    /// The settings currently in effect.
+    pub fn current(&self) -> Arc<Settings> {
+        self.settings.load_full()
+    }
+
+    /// Apply the reloadable part of `new`. Returns the settings whose
+    /// change was refused because it needs a restart.
+    pub fn apply(&self, new: Settings) -> Vec<String> {
+        let current = self.settings.load_full();
+        let refused = structural_changes(&current, &new);
+        for setting in &refused {
+            tracing::warn!(setting, "Ignoring a change that requires a restart");
+        }
+
+        let mut next = Settings::clone(&current);
+        copy_reloadable(&new, &mut next);
+
+        self.email_client.set_timeout(next.email_client.timeout());
+        self.rate_limiter.set_limits(
+            next.rate_limit.per_ip.limit(),
+            next.rate_limit.per_email.limit(),
+        );
+        if next.tracing.log_filter != current.tracing.log_filter {
+            if let Err(e) = set_log_filter(&next.tracing.log_filter) {
+                tracing::error!(error.cause_chain = ?e, "Failed to change the log filter");
+            }
+        }
+        self.settings.store(Arc::new(next));
+        tracing::info!("Reloaded the configuration");
+        refused
+    }
+
+    /// Load the configuration again and apply it.
+    pub fn reload(&self) {
+        match get_configuration() {
+            Ok(settings) => {
+                self.apply(settings);
+            }
+            Err(e) => {
+                tracing::error!(error.cause_chain = ?e, "Kept the current configuration, the new one is invalid")
+            }
+        }
+    }
+}
+
+/// The only settings a reload applies.
+fn copy_reloadable(from: &Settings, to: &mut Settings) {
+    to.email_client.timeout_milliseconds = from.email_client.timeout_milliseconds;
+    to.rate_limit.per_ip = from.rate_limit.per_ip.clone();
+    to.rate_limit.per_email = from.rate_limit.per_email.clone();
+    to.health = from.health.clone();
+    to.tracing.log_filter = from.tracing.log_filter.clone();
+}
+
+/// Every setting that differs between `current` and `new`, apart from the
+/// reloadable ones.
+fn structural_changes(current: &Settings, new: &Settings) -> Vec<String> {
+    let mut unchanged = new.clone();
+    copy_reloadable(current, &mut unchanged);
+    let mut changed = Vec::new();
+    match (
+        serde_json::to_value(current),
+        serde_json::to_value(&unchanged),
+    ) {
+        (Ok(current), Ok(new)) => changed_keys("", &current, &new, &mut changed),
+        (Err(e), _) | (_, Err(e)) => {
+            tracing::error!(error.cause_chain = ?e, "Failed to compare the settings");
+            changed.push(String::new());
+        }
+    }
+    changed.extend(changed_secrets(current, &unchanged));
+    changed.sort();
+    changed
+}
+
+fn changed_keys(path: &str, current: &Value, new: &Value, changed: &mut Vec<String>) {
+    match (current, new) {
+        (Value::Object(current), Value::Object(new)) => {
+            let keys: BTreeSet<_> = current.keys().chain(new.keys()).collect();
+            for key in keys {
+                let path = match path {
+                    "" => key.to_owned(),
+                    path => format!("{path}.{key}"),
+                };
+                changed_keys(
+                    &path,
+                    current.get(key).unwrap_or(&Value::Null),
+                    new.get(key).unwrap_or(&Value::Null),
+                    changed,
+                );
+            }
+        }
+        (current, new) if current != new => changed.push(path.to_owned()),
+        _ => {}
+    }
+}
+
+/// Secrets are not serialised, they are compared here instead. Those that
+/// refer to a provider were resolved at startup and are left out.
+fn changed_secrets(current: &Settings, new: &Settings) -> Vec<String> {
+    let (mut current, mut new) = (current.clone(), new.clone());
+    let mut changed: Vec<String> = secret_settings(&mut current)
+        .into_iter()
+        .zip(secret_settings(&mut new))
+        .filter(|((_, current), (_, new))| {
+            !is_reference(new.expose_secret()) && current.expose_secret() != new.expose_secret()
+        })
+        .map(|((setting, _), _)| setting.to_owned())
+        .collect();
+    if let (Some(current), Some(new)) = (&current.secrets.vault, &new.secrets.vault) {
+        if current.token.expose_secret() != new.token.expose_secret() {
+            changed.push("secrets.vault.token".to_owned());
+        }
+    }
+    changed
+}
+
+/// Reload the configuration on SIGHUP and, if `watch` is set, whenever a
+/// file in the configuration directory changes, until shutdown.
+pub async fn reload_on_change(reloader: Reloader, watch: bool, shutdown: Shutdown) {
+    let (changes, mut changed) = mpsc::channel(1);
+    // Dropping the watcher stops it, keep it until we are done.
+    let _watcher = if watch {
+        match watch_directories(&[&configuration_directory()], changes) {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                tracing::error!(error.cause_chain = ?e, "Failed to watch the configuration directory");
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let mut hangup = Hangup::new();
+
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => return,
+            Some(()) = changed.recv() => {
+                debounce(&mut changed).await;
+                tracing::info!("The configuration changed on disk");
+            }
+            _ = hangup.recv() => tracing::info!("Received SIGHUP"),
+        }
+        reloader.reload();
+    }
+}
+
+/// Send on `changes` whenever a file in one of `directories` is written,
+/// created or replaced, for as long as the returned watcher is kept.
+pub(crate) fn watch_directories(
+    directories: &[&Path],
+    changes: mpsc::Sender<()>,
+) -> Result<notify::RecommendedWatcher, anyhow::Error> {
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if event.is_ok_and(|e| e.kind.is_modify() || e.kind.is_create() || e.kind.is_remove()) {
+            // A reload is already pending if the channel is full.
+            let _ = changes.try_send(());
+        }
+    })?;
+    for directory in directories {
+        watcher.watch(directory, RecursiveMode::NonRecursive)?;
+    }
+    Ok(watcher)
+}
+
+/// Wait for the writes that follow a change to settle, and swallow the
+/// notifications they caused.
+pub(crate) async fn debounce(changed: &mut mpsc::Receiver<()>) {
+    tokio::time::sleep(DEBOUNCE).await;
+    while changed.try_recv().is_ok() {}
+}
+
+/// Resolves on every SIGHUP; never where there is no such signal.
+struct Hangup {
+    #[cfg(unix)]
+    signal: Option<tokio::signal::unix::Signal>,
+}
+
+impl Hangup {
+    fn new() -> Self {
+        #[cfg(unix)]
+        let signal = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+            .map_err(
+                |e| tracing::error!(error.cause_chain = ?e, "Failed to install the SIGHUP handler"),
+            )
+            .ok();
+        Self {
+            #[cfg(unix)]
+            signal,
+        }
+    }
+
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(signal) = &mut self.signal {
+            signal.recv().await;
+            return;
+        }
+        std::future::pending::<()>().await
+    }
+}
@@ -0,0 +1,106 @@
+use axum::extract::Request;
+use axum::http::{HeaderName, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use uuid::Uuid;
+
+pub static X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longer IDs are replaced rather than trusted.
+const MAX_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: RequestId;
+}
+
+/// Identifies a request in logs and in error responses.
+#[derive(Debug, Clone)]
+pub struct RequestId(String);
+
+impl RequestId {
+    fn generate() -> Self {
+        Self(Uuid::new_v4().to_string())
+    }
+
+    /// Accept an ID assigned upstream, e.g. by the load balancer, as long
+    /// as it is safe to log and to echo back: short, and made of
+    /// alphanumerics, `-`, `_`, `.` and `:` only.
+    fn parse(value: &HeaderValue) -> Option<Self> {
+        let value = value.to_str().ok()?;
+        let valid = !value.is_empty()
+            && value.len() <= MAX_LENGTH
+            && value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'));
+        valid.then(|| Self(value.to_owned()))
+    }
+
+    /// The ID of the request being handled by the current task, if any.
+    pub fn current() -> Option<Self> {
+        REQUEST_ID.try_with(Clone::clone).ok()
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Middleware assigning an ID to every request.
+///
+/// A valid `X-Request-Id` sent by the client is kept, otherwise a new ID
+/// is generated; either way it is returned in the `X-Request-Id` response
+/// header.
+///
+/// The ID is stored in the request extensions, where the trace span picks
+/// it up, and is available to the rest of the handling task through
+/// [`RequestId::current`].
+pub async fn assign_request_id(mut request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(&X_REQUEST_ID)
+        .and_then(RequestId::parse)
+        .unwrap_or_else(RequestId::generate);
+    request.extensions_mut().insert(request_id.clone());
+    let header_value =
+        HeaderValue::from_str(request_id.as_str()).expect("Request IDs are valid header values");
+
+    let mut response = REQUEST_ID.scope(request_id, next.run(request)).await;
+    response
+        .headers_mut()
+        .insert(X_REQUEST_ID.clone(), header_value);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(value: &str) -> Option<String> {
+        RequestId::parse(&HeaderValue::from_str(value).unwrap()).map(|id| id.0)
+    }
+
+    #[test]
+    fn ids_from_load_balancers_are_accepted() {
+        for id in [
+            "3f2b7c1e-9a4d-4e8f-b6a1-2c3d4e5f6a7b",
+            "1-67891233-abcdef012345678912345678",
+            "req_01HZX3.a:b",
+        ] {
+            assert_eq!(parse(id).as_deref(), Some(id));
+        }
+    }
+
+    #[test]
+    fn unsafe_or_oversized_ids_are_rejected() {
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("has space"), None);
+        assert_eq!(parse("<script>"), None);
+        assert_eq!(parse(&"a".repeat(MAX_LENGTH + 1)), None);
+    }
+}
@@ -0,0 +1,98 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use sqlx::PgPool;
+
+use crate::authentication::AdminUser;
+use crate::blocked_domains::{delete_blocked_domain, store_blocked_domain};
+use crate::domain::DomainPolicy;
+use crate::problem_details::ProblemDetails;
+use crate::routes::error_chain_format;
+
+#[tracing::instrument(name = "List blocked domains", skip(_admin, domain_policy))]
+pub async fn list_blocked_domains(
+    _admin: AdminUser,
+    State(domain_policy): State<Arc<DomainPolicy>>,
+) -> impl IntoResponse {
+    Json(domain_policy.denied_domains())
+}
+
+#[tracing::instrument(name = "Block an email domain", skip(admin, pool, domain_policy))]
+pub async fn block_domain(
+    admin: AdminUser,
+    State(pool): State<PgPool>,
+    State(domain_policy): State<Arc<DomainPolicy>>,
+    Path(domain): Path<String>,
+) -> Result<StatusCode, BlockedDomainError> {
+    if domain_policy.is_configured(&domain) {
+        return Ok(StatusCode::OK);
+    }
+    let stored = store_blocked_domain(&pool, &domain)
+        .await
+        .context("Failed to store a blocked domain.")?;
+    domain_policy.deny(&domain);
+    if stored {
+        tracing::info!(user_id = %admin.user_id, "Added {} to the deny list", domain);
+        Ok(StatusCode::CREATED)
+    } else {
+        Ok(StatusCode::OK)
+    }
+}
+
+#[tracing::instrument(name = "Unblock an email domain", skip(admin, pool, domain_policy))]
+pub async fn unblock_domain(
+    admin: AdminUser,
+    State(pool): State<PgPool>,
+    State(domain_policy): State<Arc<DomainPolicy>>,
+    Path(domain): Path<String>,
+) -> Result<StatusCode, BlockedDomainError> {
+    if domain_policy.is_configured(&domain) {
+        return Err(BlockedDomainError::Configured);
+    }
+    let deleted = delete_blocked_domain(&pool, &domain)
+        .await
+        .context("Failed to delete a blocked domain.")?;
+    // Also when it was not stored: another instance may have deleted it.
+    let undenied = domain_policy.undeny(&domain);
+    if deleted || undenied {
+        tracing::info!(user_id = %admin.user_id, "Removed {} from the deny list", domain);
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Ok(StatusCode::NOT_FOUND)
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum BlockedDomainError {
+    #[error("The domain is on the deny list of the configuration, remove it there.")]
+    Configured,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for BlockedDomainError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_format(self, f)
+    }
+}
+
+impl IntoResponse for BlockedDomainError {
+    fn into_response(self) -> Response {
+        match self {
+            BlockedDomainError::Configured => ProblemDetails::new(
+                StatusCode::CONFLICT,
+                "configured-domain",
+                "Domain Denied by the Configuration",
+            )
+            .with_detail(self.to_string())
+            .into_response(),
+            BlockedDomainError::UnexpectedError(_) => {
+                ProblemDetails::unexpected(&self).into_response()
+            }
+        }
+    }
+}
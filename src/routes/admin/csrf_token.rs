@@ -0,0 +1,31 @@
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::{header, HeaderValue};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+
+use crate::authentication::AdminUser;
+use crate::configuration::CsrfMode;
+use crate::csrf::Csrf;
+
+#[derive(Serialize)]
+pub struct CsrfToken {
+    csrf_token: String,
+}
+
+/// Hand out the token admin forms must send back, also setting it as a
+/// cookie in double-submit mode.
+#[tracing::instrument(name = "Issue a CSRF token", skip(admin, csrf), fields(user_id = %admin.user_id))]
+pub async fn issue_csrf_token(admin: AdminUser, State(csrf): State<Arc<Csrf>>) -> Response {
+    let csrf_token = csrf.issue_token(&admin.username);
+    let cookie = (csrf.mode() == CsrfMode::DoubleSubmit).then(|| csrf.cookie(&csrf_token));
+    let mut response = Json(CsrfToken { csrf_token }).into_response();
+    let headers = response.headers_mut();
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    if let Some(cookie) = cookie {
+        headers.insert(header::SET_COOKIE, cookie);
+    }
+    response
+}
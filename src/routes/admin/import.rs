@@ -0,0 +1,98 @@
+use axum::body::Body;
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use futures_util::TryStreamExt;
+use serde::Deserialize;
+use sqlx::PgPool;
+use std::sync::Arc;
+use tokio_util::io::StreamReader;
+
+use crate::authentication::AdminUser;
+use crate::domain::DomainPolicy;
+use crate::email_client::EmailClient;
+use crate::problem_details::ProblemDetails;
+use crate::routes::error_chain_format;
+use crate::startup::ApplicationBaseUrl;
+use crate::subscriber_import::{import_subscribers, ImportError, ImportOptions, ImportStatus};
+
+#[derive(Deserialize, Debug)]
+pub struct ImportParameters {
+    #[serde(default)]
+    status: ImportStatus,
+    #[serde(default)]
+    send_confirmation: bool,
+}
+
+/// Import subscribers from a CSV request body, streamed as it arrives.
+#[tracing::instrument(
+    name = "Upload subscribers",
+    skip(admin, pool, domain_policy, email_client, base_url, body),
+    fields(user_id = %admin.user_id)
+)]
+pub async fn upload_subscribers(
+    admin: AdminUser,
+    State(pool): State<PgPool>,
+    State(domain_policy): State<Arc<DomainPolicy>>,
+    State(email_client): State<EmailClient>,
+    State(base_url): State<ApplicationBaseUrl>,
+    params: Result<Query<ImportParameters>, QueryRejection>,
+    body: Body,
+) -> Result<impl IntoResponse, UploadError> {
+    let Query(params) = params?;
+    let options = ImportOptions::new(params.status, params.send_confirmation)?;
+    let input = StreamReader::new(body.into_data_stream().map_err(std::io::Error::other));
+    let report = import_subscribers(
+        input,
+        &pool,
+        &domain_policy,
+        &email_client,
+        &base_url,
+        &options,
+    )
+    .await?;
+    Ok(Json(report))
+}
+
+#[derive(thiserror::Error)]
+pub enum UploadError {
+    #[error(transparent)]
+    InvalidParameters(#[from] QueryRejection),
+    #[error(transparent)]
+    ImportFailed(#[from] ImportError),
+}
+
+impl std::fmt::Debug for UploadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_format(self, f)
+    }
+}
+
+impl IntoResponse for UploadError {
+    fn into_response(self) -> Response {
+        match &self {
+            UploadError::InvalidParameters(rejection) => ProblemDetails::new(
+                rejection.status(),
+                "invalid-parameters",
+                "Invalid Query Parameters",
+            )
+            .with_detail(rejection.body_text())
+            .into_response(),
+            UploadError::ImportFailed(ImportError::InvalidOptions(detail)) => ProblemDetails::new(
+                StatusCode::BAD_REQUEST,
+                "invalid-parameters",
+                "Invalid Query Parameters",
+            )
+            .with_detail(*detail)
+            .into_response(),
+            UploadError::ImportFailed(
+                e @ (ImportError::MissingColumns | ImportError::ReadError(_)),
+            ) => ProblemDetails::new(StatusCode::BAD_REQUEST, "invalid-csv", "Invalid CSV")
+                .with_detail(e.to_string())
+                .into_response(),
+            UploadError::ImportFailed(_) => ProblemDetails::unexpected(&self).into_response(),
+        }
+    }
+}
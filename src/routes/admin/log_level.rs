@@ -0,0 +1,191 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Context;
+use arc_swap::ArcSwap;
+use axum::extract::rejection::JsonRejection;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::task::AbortHandle;
+use tracing_subscriber::EnvFilter;
+
+use crate::authentication::AdminUser;
+use crate::configuration::Settings;
+use crate::problem_details::ProblemDetails;
+use crate::routes::{error_chain_format, invalid_body};
+use crate::telemetry::{current_log_filter, set_log_filter};
+
+/// The longest an override can last before going back to the default.
+const MAX_TTL_SECONDS: u64 = 7 * 24 * 60 * 60;
+
+/// The log filter set through the admin API, on top of the configured one.
+pub struct LogLevelOverride {
+    settings: Arc<ArcSwap<Settings>>,
+    revert: Mutex<Revert>,
+}
+
+#[derive(Default)]
+struct Revert {
+    /// Bumped by every change, so that a revert that was due when a newer
+    /// filter was set leaves it alone.
+    generation: u64,
+    pending: Option<(DateTime<Utc>, AbortHandle)>,
+}
+
+impl LogLevelOverride {
+    pub fn new(settings: Arc<ArcSwap<Settings>>) -> Self {
+        Self {
+            settings,
+            revert: Mutex::new(Revert::default()),
+        }
+    }
+
+    fn default_filter(&self) -> String {
+        self.settings.load().tracing.log_filter.clone()
+    }
+
+    /// Swap the filter, going back to the configured one after `ttl`.
+    fn set(self: &Arc<Self>, filter: &str, ttl: Option<Duration>) -> Result<(), anyhow::Error> {
+        let revert_at = ttl
+            .map(|ttl| {
+                TimeDelta::from_std(ttl)
+                    .ok()
+                    .and_then(|ttl| Utc::now().checked_add_signed(ttl))
+                    .context("The TTL of the log filter is out of range.")
+            })
+            .transpose()?;
+        // Held while the filter is swapped, so that changes and reverts
+        // happen in order.
+        let mut revert = self.revert.lock().unwrap();
+        set_log_filter(filter)?;
+        revert.generation += 1;
+        if let Some((_, task)) = revert.pending.take() {
+            task.abort();
+        }
+        if let (Some(ttl), Some(revert_at)) = (ttl, revert_at) {
+            let generation = revert.generation;
+            let this = Arc::clone(self);
+            let task = tokio::spawn(async move {
+                tokio::time::sleep(ttl).await;
+                this.revert_to_default(generation);
+            });
+            revert.pending = Some((revert_at, task.abort_handle()));
+        }
+        Ok(())
+    }
+
+    fn revert_to_default(&self, generation: u64) {
+        let mut revert = self.revert.lock().unwrap();
+        if revert.generation != generation {
+            return;
+        }
+        revert.pending = None;
+        let default = self.default_filter();
+        match set_log_filter(&default) {
+            Ok(()) => tracing::info!(filter = %default, "Reverted the log filter"),
+            Err(e) => tracing::error!(error.cause_chain = ?e, "Failed to revert the log filter"),
+        }
+    }
+
+    fn status(&self) -> LogLevel {
+        LogLevel {
+            filter: current_log_filter().unwrap_or_default(),
+            default: self.default_filter(),
+            revert_at: self
+                .revert
+                .lock()
+                .unwrap()
+                .pending
+                .as_ref()
+                .map(|(at, _)| *at),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct LogLevel {
+    filter: String,
+    /// What the filter goes back to once `revert_at` is reached.
+    default: String,
+    revert_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+pub struct LogLevelUpdate {
+    /// `RUST_LOG`-style directives, e.g. `info,zero2prod=debug`.
+    filter: String,
+    /// Keep the filter until restart when missing, at most a week.
+    ttl_seconds: Option<u64>,
+}
+
+#[tracing::instrument(name = "Get the log level", skip(_admin, log_level))]
+pub async fn get_log_level(
+    _admin: AdminUser,
+    State(log_level): State<Arc<LogLevelOverride>>,
+) -> Json<LogLevel> {
+    Json(log_level.status())
+}
+
+#[tracing::instrument(name = "Change the log level", skip(admin, log_level, body))]
+pub async fn set_log_level(
+    admin: AdminUser,
+    State(log_level): State<Arc<LogLevelOverride>>,
+    body: Result<Json<LogLevelUpdate>, JsonRejection>,
+) -> Result<Json<LogLevel>, LogLevelError> {
+    let Json(update) = body?;
+    EnvFilter::try_new(&update.filter).map_err(|e| LogLevelError::InvalidFilter(e.to_string()))?;
+    if update
+        .ttl_seconds
+        .is_some_and(|ttl| ttl == 0 || ttl > MAX_TTL_SECONDS)
+    {
+        return Err(LogLevelError::InvalidFilter(format!(
+            "`ttl_seconds` must be between 1 and {MAX_TTL_SECONDS}."
+        )));
+    }
+    log_level.set(&update.filter, update.ttl_seconds.map(Duration::from_secs))?;
+    tracing::info!(
+        user_id = %admin.user_id,
+        filter = %update.filter,
+        ttl_seconds = ?update.ttl_seconds,
+        "Changed the log filter"
+    );
+    Ok(Json(log_level.status()))
+}
+
+#[derive(thiserror::Error)]
+pub enum LogLevelError {
+    #[error(transparent)]
+    InvalidBody(#[from] JsonRejection),
+    #[error("{0}")]
+    InvalidFilter(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for LogLevelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_format(self, f)
+    }
+}
+
+impl IntoResponse for LogLevelError {
+    fn into_response(self) -> Response {
+        match &self {
+            LogLevelError::InvalidBody(rejection) => {
+                invalid_body(rejection.status(), rejection.body_text()).into_response()
+            }
+            LogLevelError::InvalidFilter(detail) => ProblemDetails::new(
+                StatusCode::BAD_REQUEST,
+                "invalid-log-filter",
+                "Invalid Log Filter",
+            )
+            .with_detail(detail.clone())
+            .into_response(),
+            LogLevelError::UnexpectedError(_) => ProblemDetails::unexpected(&self).into_response(),
+        }
+    }
+}
@@ -0,0 +1,11 @@
+mod blocked_domains;
+mod csrf_token;
+mod import;
+mod log_level;
+mod subscribers;
+
+pub use blocked_domains::*;
+pub use csrf_token::*;
+pub use import::*;
+pub use log_level::*;
+pub use subscribers::*;
@@ -0,0 +1,557 @@
+use anyhow::Context;
+use axum::body::Body;
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures_util::Stream;
+use serde::{Deserialize, Serialize};
+use sqlx::pool::PoolConnection;
+use sqlx::{PgConnection, Postgres};
+use uuid::Uuid;
+
+use crate::authentication::AdminUser;
+use crate::problem_details::ProblemDetails;
+use crate::routes::error_chain_format;
+use crate::startup::ReadPool;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    #[default]
+    SubscribedAt,
+    Email,
+    Name,
+}
+
+impl SortField {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SortField::SubscribedAt => "subscribed_at",
+            SortField::Email => "email",
+            SortField::Name => "name",
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+impl SortOrder {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SortOrder::Asc => "asc",
+            SortOrder::Desc => "desc",
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Json,
+    Csv,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ListParameters {
+    status: Option<String>,
+    /// Case-insensitive substring matched against email and name.
+    q: Option<String>,
+    #[serde(default)]
+    sort: SortField,
+    #[serde(default)]
+    order: SortOrder,
+    limit: Option<i64>,
+    cursor: Option<String>,
+    format: Option<ExportFormat>,
+}
+
+#[derive(Serialize)]
+pub struct SubscriberSummary {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct SubscriberPage {
+    subscribers: Vec<SubscriberSummary>,
+    /// Pass as `cursor` to fetch the next page, `null` on the last page.
+    next_cursor: Option<String>,
+}
+
+/// Position after the last row of a page, for keyset pagination.
+///
+/// It remembers the ordering it was produced for, so it cannot be replayed
+/// against a different one.
+#[derive(Debug, PartialEq, Eq)]
+struct Cursor {
+    sort: SortField,
+    order: SortOrder,
+    id: Uuid,
+    sort_key: SortKey,
+}
+
+/// The value of the sort column in the last row of a page.
+#[derive(Debug, PartialEq, Eq)]
+enum SortKey {
+    Text(String),
+    Time(DateTime<Utc>),
+}
+
+impl Cursor {
+    fn after(subscriber: &SubscriberSummary, sort: SortField, order: SortOrder) -> Self {
+        let sort_key = match sort {
+            SortField::SubscribedAt => SortKey::Time(subscriber.subscribed_at),
+            SortField::Email => SortKey::Text(subscriber.email.clone()),
+            SortField::Name => SortKey::Text(subscriber.name.clone()),
+        };
+        Self {
+            sort,
+            order,
+            id: subscriber.id,
+            sort_key,
+        }
+    }
+
+    fn encode(&self) -> String {
+        let sort_key = match &self.sort_key {
+            SortKey::Text(text) => text.clone(),
+            SortKey::Time(time) => time.to_rfc3339_opts(SecondsFormat::Micros, true),
+        };
+        URL_SAFE_NO_PAD.encode(format!(
+            "{}:{}:{}:{}",
+            self.sort.as_str(),
+            self.order.as_str(),
+            self.id,
+            sort_key
+        ))
+    }
+
+    fn decode(s: &str, sort: SortField, order: SortOrder) -> Option<Self> {
+        let decoded = String::from_utf8(URL_SAFE_NO_PAD.decode(s).ok()?).ok()?;
+        let rest = decoded
+            .strip_prefix(sort.as_str())?
+            .strip_prefix(':')?
+            .strip_prefix(order.as_str())?
+            .strip_prefix(':')?;
+        let (id, sort_key) = rest.split_once(':')?;
+        let sort_key = match sort {
+            SortField::SubscribedAt => {
+                SortKey::Time(DateTime::parse_from_rfc3339(sort_key).ok()?.to_utc())
+            }
+            SortField::Email | SortField::Name => SortKey::Text(sort_key.to_string()),
+        };
+        Some(Self {
+            sort,
+            order,
+            id: id.parse().ok()?,
+            sort_key,
+        })
+    }
+}
+
+struct Filter {
+    status: Option<String>,
+    pattern: Option<String>,
+    sort: SortField,
+    order: SortOrder,
+}
+
+// Not `params`: searches are for names and email addresses.
+#[tracing::instrument(
+    name = "List subscribers",
+    skip(admin, read_pool, headers, params),
+    fields(user_id = %admin.user_id)
+)]
+pub async fn list_subscribers(
+    admin: AdminUser,
+    State(read_pool): State<ReadPool>,
+    headers: HeaderMap,
+    params: Result<Query<ListParameters>, QueryRejection>,
+) -> Result<Response, ListSubscribersError> {
+    let Query(params) = params?;
+    let filter = Filter {
+        status: params.status,
+        pattern: params.q.map(|q| format!("%{}%", escape_like(&q))),
+        sort: params.sort,
+        order: params.order,
+    };
+    let cursor = params
+        .cursor
+        .map(|c| {
+            Cursor::decode(&c, filter.sort, filter.order).ok_or(ListSubscribersError::InvalidCursor)
+        })
+        .transpose()?;
+
+    let format = params.format.unwrap_or_else(|| preferred_format(&headers));
+    let mut connection = read_pool
+        .acquire()
+        .await
+        .context("Failed to acquire a database connection.")?;
+    match format {
+        ExportFormat::Json => {
+            let limit = params
+                .limit
+                .unwrap_or(DEFAULT_PAGE_SIZE)
+                .clamp(1, MAX_PAGE_SIZE);
+            let (subscribers, next_cursor) =
+                fetch_page(&mut connection, &filter, cursor.as_ref(), limit)
+                    .await
+                    .context("Failed to fetch a page of subscribers.")?;
+            let page = SubscriberPage {
+                subscribers,
+                next_cursor: next_cursor.map(|c| c.encode()),
+            };
+            Ok(Json(page).into_response())
+        }
+        ExportFormat::Csv => {
+            let csv = Body::from_stream(export_csv(connection, filter, cursor));
+            Ok((
+                [
+                    (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+                    (
+                        header::CONTENT_DISPOSITION,
+                        r#"attachment; filename="subscribers.csv""#,
+                    ),
+                ],
+                csv,
+            )
+                .into_response())
+        }
+    }
+}
+
+fn preferred_format(headers: &HeaderMap) -> ExportFormat {
+    let accepts_csv = headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("text/csv"));
+    if accepts_csv {
+        ExportFormat::Csv
+    } else {
+        ExportFormat::Json
+    }
+}
+
+/// Escape the wildcards of a `LIKE` pattern.
+fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Returns the page and, if there may be more rows, the cursor to continue from.
+#[tracing::instrument(name = "Fetch a page of subscribers", skip(connection, filter, cursor))]
+async fn fetch_page(
+    connection: &mut PgConnection,
+    filter: &Filter,
+    cursor: Option<&Cursor>,
+    limit: i64,
+) -> Result<(Vec<SubscriberSummary>, Option<Cursor>), sqlx::Error> {
+    let status = filter.status.as_deref();
+    let pattern = filter.pattern.as_deref();
+    let (after_text, after_time) = match cursor.map(|c| &c.sort_key) {
+        Some(SortKey::Text(text)) => (Some(text.as_str()), None),
+        Some(SortKey::Time(time)) => (None, Some(*time)),
+        None => (None, None),
+    };
+    let after_id = cursor.map(|c| c.id);
+    // One query per ordering: the cursor is compared as a row on the sort
+    // column, so that an index on `(column, id)` serves both the condition
+    // and the ordering.
+    let subscribers = match (filter.sort, filter.order) {
+        (SortField::SubscribedAt, SortOrder::Asc) => {
+            sqlx::query_as!(
+                SubscriberSummary,
+                r#"
+                SELECT id, email, name, status, subscribed_at FROM subscriptions
+                WHERE ($1::text IS NULL OR status = $1)
+                AND ($2::text IS NULL OR email ILIKE $2 OR name ILIKE $2)
+                AND ($3::timestamptz IS NULL OR (subscribed_at, id) > ($3, $4::uuid))
+                ORDER BY subscribed_at ASC, id ASC
+                LIMIT $5
+                "#,
+                status,
+                pattern,
+                after_time,
+                after_id,
+                limit
+            )
+            .fetch_all(connection)
+            .await?
+        }
+        (SortField::SubscribedAt, SortOrder::Desc) => {
+            sqlx::query_as!(
+                SubscriberSummary,
+                r#"
+                SELECT id, email, name, status, subscribed_at FROM subscriptions
+                WHERE ($1::text IS NULL OR status = $1)
+                AND ($2::text IS NULL OR email ILIKE $2 OR name ILIKE $2)
+                AND ($3::timestamptz IS NULL OR (subscribed_at, id) < ($3, $4::uuid))
+                ORDER BY subscribed_at DESC, id DESC
+                LIMIT $5
+                "#,
+                status,
+                pattern,
+                after_time,
+                after_id,
+                limit
+            )
+            .fetch_all(connection)
+            .await?
+        }
+        (SortField::Email, SortOrder::Asc) => {
+            sqlx::query_as!(
+                SubscriberSummary,
+                r#"
+                SELECT id, email, name, status, subscribed_at FROM subscriptions
+                WHERE ($1::text IS NULL OR status = $1)
+                AND ($2::text IS NULL OR email ILIKE $2 OR name ILIKE $2)
+                AND ($3::text IS NULL OR (email, id) > ($3, $4::uuid))
+                ORDER BY email ASC, id ASC
+                LIMIT $5
+                "#,
+                status,
+                pattern,
+                after_text,
+                after_id,
+                limit
+            )
+            .fetch_all(connection)
+            .await?
+        }
+        (SortField::Email, SortOrder::Desc) => {
+            sqlx::query_as!(
+                SubscriberSummary,
+                r#"
+                SELECT id, email, name, status, subscribed_at FROM subscriptions
+                WHERE ($1::text IS NULL OR status = $1)
+                AND ($2::text IS NULL OR email ILIKE $2 OR name ILIKE $2)
+                AND ($3::text IS NULL OR (email, id) < ($3, $4::uuid))
+                ORDER BY email DESC, id DESC
+                LIMIT $5
+                "#,
+                status,
+                pattern,
+                after_text,
+                after_id,
+                limit
+            )
+            .fetch_all(connection)
+            .await?
+        }
+        (SortField::Name, SortOrder::Asc) => {
+            sqlx::query_as!(
+                SubscriberSummary,
+                r#"
+                SELECT id, email, name, status, subscribed_at FROM subscriptions
+                WHERE ($1::text IS NULL OR status = $1)
+                AND ($2::text IS NULL OR email ILIKE $2 OR name ILIKE $2)
+                AND ($3::text IS NULL OR (name, id) > ($3, $4::uuid))
+                ORDER BY name ASC, id ASC
+                LIMIT $5
+                "#,
+                status,
+                pattern,
+                after_text,
+                after_id,
+                limit
+            )
+            .fetch_all(connection)
+            .await?
+        }
+        (SortField::Name, SortOrder::Desc) => {
+            sqlx::query_as!(
+                SubscriberSummary,
+                r#"
+                SELECT id, email, name, status, subscribed_at FROM subscriptions
+                WHERE ($1::text IS NULL OR status = $1)
+                AND ($2::text IS NULL OR email ILIKE $2 OR name ILIKE $2)
+                AND ($3::text IS NULL OR (name, id) < ($3, $4::uuid))
+                ORDER BY name DESC, id DESC
+                LIMIT $5
+                "#,
+                status,
+                pattern,
+                after_text,
+                after_id,
+                limit
+            )
+            .fetch_all(connection)
+            .await?
+        }
+    };
+    let next_cursor = match subscribers.last() {
+        Some(last) if subscribers.len() as i64 == limit => {
+            Some(Cursor::after(last, filter.sort, filter.order))
+        }
+        _ => None,
+    };
+    Ok((subscribers, next_cursor))
+}
+
+/// Every subscriber matching the filter, starting at `cursor` if given,
+/// fetched and written out a page at a time.
+fn export_csv(
+    connection: PoolConnection<Postgres>,
+    filter: Filter,
+    cursor: Option<Cursor>,
+) -> impl Stream<Item = Result<Vec<u8>, anyhow::Error>> {
+    let export = CsvExport {
+        connection,
+        filter,
+        cursor,
+        header_written: false,
+    };
+    futures_util::stream::unfold(Some(export), |export| async move {
+        let mut export = export?;
+        match export.next_page().await {
+            Ok((chunk, more)) => Some((Ok(chunk), more.then_some(export))),
+            Err(e) => {
+                // The response is already under way, all we can do is cut it short.
+                tracing::error!(error.cause_chain = ?e, "Failed to export subscribers as CSV");
+                Some((Err(e), None))
+            }
+        }
+    })
+}
+
+struct CsvExport {
+    connection: PoolConnection<Postgres>,
+    filter: Filter,
+    cursor: Option<Cursor>,
+    header_written: bool,
+}
+
+impl CsvExport {
+    /// The CSV of the next page, and whether more may follow.
+    async fn next_page(&mut self) -> Result<(Vec<u8>, bool), anyhow::Error> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        if !self.header_written {
+            writer.write_record(["id", "email", "name", "status", "subscribed_at"])?;
+            self.header_written = true;
+        }
+        let (subscribers, next_cursor) = fetch_page(
+            &mut self.connection,
+            &self.filter,
+            self.cursor.as_ref(),
+            MAX_PAGE_SIZE,
+        )
+        .await
+        .context("Failed to fetch a page of subscribers.")?;
+        for s in subscribers {
+            writer.write_record([
+                s.id.to_string(),
+                s.email,
+                s.name,
+                s.status,
+                s.subscribed_at.to_rfc3339(),
+            ])?;
+        }
+        self.cursor = next_cursor;
+        Ok((writer.into_inner()?, self.cursor.is_some()))
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum ListSubscribersError {
+    #[error(transparent)]
+    InvalidParameters(#[from] QueryRejection),
+    #[error("The cursor is malformed or belongs to a different ordering.")]
+    InvalidCursor,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ListSubscribersError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_format(self, f)
+    }
+}
+
+impl IntoResponse for ListSubscribersError {
+    fn into_response(self) -> Response {
+        match self {
+            ListSubscribersError::InvalidParameters(rejection) => ProblemDetails::new(
+                rejection.status(),
+                "invalid-parameters",
+                "Invalid Query Parameters",
+            )
+            .with_detail(rejection.body_text())
+            .into_response(),
+            ListSubscribersError::InvalidCursor => {
+                ProblemDetails::new(StatusCode::BAD_REQUEST, "invalid-cursor", "Invalid Cursor")
+                    .with_detail(self.to_string())
+                    .into_response()
+            }
+            ListSubscribersError::UnexpectedError(_) => {
+                ProblemDetails::unexpected(&self).into_response()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape_like, Cursor, SortField, SortKey, SortOrder};
+
+    #[test]
+    fn cursors_round_trip() {
+        let cursor = Cursor {
+            sort: SortField::Name,
+            order: SortOrder::Asc,
+            id: uuid::Uuid::new_v4(),
+            sort_key: SortKey::Text("le: guin".into()),
+        };
+        let decoded = Cursor::decode(&cursor.encode(), SortField::Name, SortOrder::Asc);
+        assert_eq!(decoded, Some(cursor));
+    }
+
+    #[test]
+    fn subscription_time_cursors_keep_microseconds() {
+        let time = chrono::DateTime::parse_from_rfc3339("2024-11-01T09:00:00.123456Z")
+            .unwrap()
+            .to_utc();
+        let cursor = Cursor {
+            sort: SortField::SubscribedAt,
+            order: SortOrder::Desc,
+            id: uuid::Uuid::new_v4(),
+            sort_key: SortKey::Time(time),
+        };
+        let decoded = Cursor::decode(&cursor.encode(), SortField::SubscribedAt, SortOrder::Desc);
+        assert_eq!(decoded, Some(cursor));
+    }
+
+    #[test]
+    fn cursors_are_bound_to_their_ordering() {
+        let cursor = Cursor {
+            sort: SortField::Email,
+            order: SortOrder::Desc,
+            id: uuid::Uuid::new_v4(),
+            sort_key: SortKey::Text("ursula@example.com".into()),
+        };
+        assert!(Cursor::decode(&cursor.encode(), SortField::Email, SortOrder::Asc).is_none());
+        assert!(Cursor::decode(&cursor.encode(), SortField::Name, SortOrder::Desc).is_none());
+    }
+
+    #[test]
+    fn like_wildcards_are_escaped() {
+        assert_eq!(escape_like(r"50%_off\"), r"50\%\_off\\");
+    }
+}
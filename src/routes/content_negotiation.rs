@@ -0,0 +1,80 @@
+use axum::async_trait;
+use axum::extract::{FromRequest, Request};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::Form;
+use axum::Json;
+use serde::de::DeserializeOwned;
+
+use crate::problem_details::ProblemDetails;
+
+/// Accepts either a urlencoded form or a JSON body, based on `Content-Type`.
+pub struct FormOrJson<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for FormOrJson<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ProblemDetails;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if has_json_content_type(req.headers()) {
+            let Json(value) = Json::<T>::from_request(req, state)
+                .await
+                .map_err(|e| invalid_body(e.status(), e.body_text()))?;
+            Ok(Self(value))
+        } else {
+            let Form(value) = Form::<T>::from_request(req, state)
+                .await
+                .map_err(|e| invalid_body(e.status(), e.body_text()))?;
+            Ok(Self(value))
+        }
+    }
+}
+
+pub(crate) fn invalid_body(status: StatusCode, detail: String) -> ProblemDetails {
+    ProblemDetails::new(status, "invalid-body", "Invalid Request Body").with_detail(detail)
+}
+
+fn has_json_content_type(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<mime::Mime>().ok())
+        .is_some_and(|mime| is_json(&mime))
+}
+
+fn is_json(mime: &mime::Mime) -> bool {
+    mime.type_() == mime::APPLICATION
+        && (mime.subtype() == mime::JSON || mime.suffix() == Some(mime::JSON))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::has_json_content_type;
+    use axum::http::HeaderMap;
+
+    fn content_type(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn form_posts_are_not_json() {
+        assert!(!has_json_content_type(&content_type(
+            "application/x-www-form-urlencoded"
+        )));
+    }
+
+    #[test]
+    fn json_content_types_are_recognised() {
+        assert!(has_json_content_type(&content_type(
+            "application/json; charset=utf-8"
+        )));
+        assert!(has_json_content_type(&content_type(
+            "application/merge-patch+json"
+        )));
+    }
+}
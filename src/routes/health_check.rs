@@ -1,5 +1,129 @@
-use axum::{http::StatusCode, response::IntoResponse};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use axum::extract::State;
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::configuration::HealthSettings;
+use crate::email_client::EmailClient;
+use crate::migration::verify_schema;
 
 pub async fn health_check() -> impl IntoResponse {
     StatusCode::OK
 }
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Up,
+    Down,
+}
+
+#[derive(Serialize)]
+pub struct Health {
+    status: Status,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    checks: BTreeMap<&'static str, ComponentHealth>,
+}
+
+#[derive(Serialize)]
+pub struct ComponentHealth {
+    status: Status,
+    latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'static str>,
+}
+
+/// The process is up and serving requests.
+pub async fn liveness() -> Json<Health> {
+    Json(Health {
+        status: Status::Up,
+        checks: BTreeMap::new(),
+    })
+}
+
+/// The instance can do useful work: its dependencies are reachable and
+/// the schema matches the code.
+#[tracing::instrument(name = "Readiness check", skip_all)]
+pub async fn readiness(
+    State(pool): State<PgPool>,
+    State(email_client): State<EmailClient>,
+    State(settings): State<HealthSettings>,
+) -> impl IntoResponse {
+    let timeout = settings.timeout();
+    let (database, migrations, email_provider) = tokio::join!(
+        probe("database", timeout, async {
+            sqlx::query("SELECT 1").execute(&pool).await?;
+            Ok(())
+        }),
+        probe("migrations", timeout, async {
+            let mut connection = pool.acquire().await?;
+            verify_schema(&mut connection).await?;
+            Ok(())
+        }),
+        async {
+            if settings.check_email_provider {
+                Some(
+                    probe("email_provider", timeout, async {
+                        Ok(email_client.ping().await?)
+                    })
+                    .await,
+                )
+            } else {
+                None
+            }
+        }
+    );
+
+    let checks: BTreeMap<_, _> = [Some(database), Some(migrations), email_provider]
+        .into_iter()
+        .flatten()
+        .collect();
+    let status = if checks.values().all(|c| c.status == Status::Up) {
+        Status::Up
+    } else {
+        Status::Down
+    };
+    let code = match status {
+        Status::Up => StatusCode::OK,
+        Status::Down => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (code, Json(Health { status, checks }))
+}
+
+async fn probe(
+    name: &'static str,
+    timeout: Duration,
+    check: impl Future<Output = Result<(), anyhow::Error>>,
+) -> (&'static str, ComponentHealth) {
+    let started = Instant::now();
+    let outcome = tokio::time::timeout(timeout, check).await;
+    let latency_ms = started.elapsed().as_millis() as u64;
+    let error = match outcome {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => {
+            tracing::warn!(component = name, error.cause_chain = ?e, "Readiness check failed");
+            Some("check failed")
+        }
+        Err(_) => {
+            tracing::warn!(component = name, "Readiness check timed out");
+            Some("timed out")
+        }
+    };
+    let status = if error.is_none() {
+        Status::Up
+    } else {
+        Status::Down
+    };
+    (
+        name,
+        ComponentHealth {
+            status,
+            latency_ms,
+            error,
+        },
+    )
+}
@@ -1,8 +1,12 @@
+mod admin;
+mod content_negotiation;
 mod health_check;
 mod newsletter;
 mod subscriptions;
 mod subscriptions_confirm;
 
+pub use admin::*;
+pub use content_negotiation::*;
 pub use health_check::*;
 pub use newsletter::*;
 pub use subscriptions::*;
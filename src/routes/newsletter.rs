@@ -1,4 +1,5 @@
 use anyhow::Context;
+use axum::extract::rejection::JsonRejection;
 use axum::extract::State;
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
@@ -8,8 +9,11 @@ use sqlx::{PgPool, Pool, Postgres};
 
 use crate::domain::SubscriberEmail;
 use crate::email_client::EmailClient;
+use crate::problem_details::ProblemDetails;
+use crate::prometheus::DeliveryQueue;
+use crate::shutdown::Shutdown;
 
-use super::error_chain_format;
+use super::{error_chain_format, invalid_body};
 
 #[derive(Deserialize)]
 pub struct BodyData {
@@ -27,18 +31,36 @@ struct ConfirmedSubscriber {
     email: SubscriberEmail,
 }
 
+/// Send the issue to every confirmed subscriber, one at a time.
+///
+/// On shutdown the email being sent is finished, within the drain timeout,
+/// and the rest are not sent.
 pub async fn publish_newsletter(
     State(pool): State<Pool<Postgres>>,
     State(email_client): State<EmailClient>,
-    Json(body): Json<BodyData>,
+    State(shutdown): State<Shutdown>,
+    body: Result<Json<BodyData>, JsonRejection>,
 ) -> Result<impl IntoResponse, PublishError> {
+    let Json(body) = body?;
     let subscribers = get_confirmed_subscribers(&pool).await?;
 
-    for subscriber in subscribers {
+    let total = subscribers.len();
+    let mut queue = DeliveryQueue::enqueue(total);
+    for (sent, subscriber) in subscribers.into_iter().enumerate() {
+        if shutdown.is_triggered() {
+            tracing::warn!(
+                sent,
+                not_sent = total - sent,
+                "Stopped sending a newsletter issue to shut down"
+            );
+            return Err(PublishError::Interrupted { sent, total });
+        }
+        queue.dequeue();
         match subscriber {
             Ok(subscriber) => {
                 email_client
                     .send_email(
+                        "newsletter",
                         &subscriber.email,
                         &body.title,
                         &body.content.html,
@@ -83,6 +105,10 @@ async fn get_confirmed_subscribers(
 
 #[derive(thiserror::Error)]
 pub enum PublishError {
+    #[error(transparent)]
+    InvalidBody(#[from] JsonRejection),
+    #[error("The server shut down after sending the newsletter to {sent} of {total} subscribers.")]
+    Interrupted { sent: usize, total: usize },
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -96,7 +122,17 @@ impl std::fmt::Debug for PublishError {
 impl IntoResponse for PublishError {
     fn into_response(self) -> axum::response::Response {
         match self {
-            PublishError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            PublishError::InvalidBody(rejection) => {
+                invalid_body(rejection.status(), rejection.body_text()).into_response()
+            }
+            PublishError::Interrupted { .. } => ProblemDetails::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "delivery-interrupted",
+                "Newsletter Delivery Interrupted",
+            )
+            .with_detail(self.to_string())
+            .into_response(),
+            PublishError::UnexpectedError(_) => ProblemDetails::unexpected(&self).into_response(),
         }
     }
 }
@@ -1,59 +1,129 @@
 use anyhow::Context;
 use axum::response::Response;
-use axum::{
-    extract::State,
-    http::StatusCode,
-    response::{Form, IntoResponse},
-};
+use axum::Json;
+use axum::{extract::State, http::StatusCode, response::IntoResponse};
 use chrono::Utc;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use sqlx::{Executor, Pool, Postgres, Transaction};
 use std::fmt::Formatter;
+use std::sync::Arc;
 use uuid::Uuid;
 
 use crate::{
-    domain::{NewSubscriber, SubscriberEmail, SubscriberName},
+    bot_defence::{BotDefence, BotSignal, Submission},
+    domain::{DomainPolicy, DomainRejection, NewSubscriber, SubscriberEmail, SubscriberName},
     email_client::EmailClient,
+    problem_details::{FieldError, ProblemDetails},
+    rate_limit::{RateLimited, RateLimiter},
     startup::ApplicationBaseUrl,
+    telemetry::Redacted,
 };
 
+use super::FormOrJson;
+
 #[derive(Deserialize)]
 pub struct FormData {
     email: String,
     name: String,
+    /// Hidden from humans, only bots fill it in.
+    #[serde(default)]
+    website: Option<String>,
+    /// Signed timestamp from `/subscriptions/form_token`.
+    #[serde(default)]
+    form_token: Option<String>,
+    /// Nonce solving the proof of work for `form_token` and `email`.
+    #[serde(default)]
+    proof_of_work: Option<String>,
+}
+
+impl FormData {
+    fn submission(&self) -> Submission<'_> {
+        Submission {
+            honeypot: self.website.as_deref(),
+            form_token: self.form_token.as_deref(),
+            proof_of_work: self.proof_of_work.as_deref(),
+            email: &self.email,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct FormToken {
+    form_token: String,
+    proof_of_work_bits: u8,
+}
+
+#[tracing::instrument(name = "Issue a signup form token", skip(bot_defence))]
+pub async fn issue_form_token(State(bot_defence): State<Arc<BotDefence>>) -> Json<FormToken> {
+    Json(FormToken {
+        form_token: bot_defence.issue_form_token(),
+        proof_of_work_bits: bot_defence.proof_of_work_bits(),
+    })
 }
 
 impl TryFrom<FormData> for NewSubscriber {
-    type Error = String;
+    type Error = SubscribeError;
 
+    /// Checks every field, so that all problems are reported at once.
     fn try_from(value: FormData) -> Result<Self, Self::Error> {
-        let name = SubscriberName::parse(value.name)?;
-        let email = SubscriberEmail::parse(value.email)?;
-        Ok(Self { email, name })
+        let name = SubscriberName::parse(value.name).map_err(|message| FieldError {
+            field: "name",
+            code: "invalid_name",
+            message,
+        });
+        let email = SubscriberEmail::parse(value.email).map_err(|message| FieldError {
+            field: "email",
+            code: "invalid_email",
+            message,
+        });
+        match (name, email) {
+            (Ok(name), Ok(email)) => Ok(Self { email, name }),
+            (name, email) => Err(SubscribeError::ValidationError(
+                name.err().into_iter().chain(email.err()).collect(),
+            )),
+        }
     }
 }
 
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pool, email_client, base_url),
+    skip(form, pool, email_client, base_url, domain_policy, rate_limiter, bot_defence),
     fields(
-        subscriber_email = %form.email,
-        subscriber_name = %form.name
+        subscriber_email = %Redacted(&form.email),
+        subscriber_name = %Redacted(&form.name)
     )
 )]
 pub async fn subscribe(
     State(base_url): State<ApplicationBaseUrl>,
     State(email_client): State<EmailClient>,
     State(pool): State<Pool<Postgres>>,
-    Form(form): Form<FormData>,
+    State(domain_policy): State<Arc<DomainPolicy>>,
+    State(rate_limiter): State<Arc<RateLimiter>>,
+    State(bot_defence): State<Arc<BotDefence>>,
+    FormOrJson(form): FormOrJson<FormData>,
 ) -> Result<impl IntoResponse, SubscribeError> {
+    let form_token = match bot_defence.check(&form.submission()) {
+        Ok(form_token) => form_token,
+        Err(signal) => return Ok(discard_automated(signal)),
+    };
+
+    let new_subscriber: NewSubscriber = form.try_into()?;
+    domain_policy.check(&new_subscriber.email)?;
+    // Before taking a connection: the Postgres rate limit store needs one too.
+    rate_limiter
+        .check_email(new_subscriber.email.as_ref())
+        .await?;
+    // Only now: a rejected submission can be corrected and sent again.
+    if let Err(signal) = bot_defence.use_token(form_token) {
+        return Ok(discard_automated(signal));
+    }
+
     let mut transaction = pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection from the pool")?;
-    let new_subscriber = form.try_into()?;
 
     let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
         .await
@@ -76,6 +146,15 @@ pub async fn subscribe(
     Ok(StatusCode::OK)
 }
 
+/// Pretend everything went fine, bots should learn nothing.
+fn discard_automated(signal: BotSignal) -> StatusCode {
+    tracing::warn!(
+        bot_signal = signal.as_str(),
+        "Discarded a subscription that looks automated"
+    );
+    StatusCode::OK
+}
+
 #[tracing::instrument(
     name = "Saving new subscriber details in the database"
     skip(new_subscriber, transaction)
@@ -96,7 +175,8 @@ pub async fn insert_subscriber(
         Utc::now(),
     );
     transaction.execute(query).await.map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
+        // Not `{:?}`: the details of a database error quote the row.
+        tracing::error!("Failed to execute query: {}", e);
         e
     })?;
     Ok(subscriber_id)
@@ -127,11 +207,17 @@ Click <a href=\"{}\">here</a> to confirm your subscription.",
         confirmation_link
     );
     email_client
-        .send_email(&new_subscriber.email, "Welcome!", &html_body, &plain_body)
+        .send_email(
+            "confirmation",
+            &new_subscriber.email,
+            "Welcome!",
+            &html_body,
+            &plain_body,
+        )
         .await
 }
 
-fn generate_subscriptions_token() -> String {
+pub fn generate_subscriptions_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
@@ -176,15 +262,29 @@ pub fn error_chain_format(
 
 #[derive(thiserror::Error)]
 pub enum SubscribeError {
-    #[error("{0}")]
-    ValidationError(String),
+    #[error("{}", join_messages(.0))]
+    ValidationError(Vec<FieldError>),
+    #[error(transparent)]
+    TooManyRequests(#[from] RateLimited),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
 
-impl From<String> for SubscribeError {
-    fn from(value: String) -> Self {
-        Self::ValidationError(value)
+fn join_messages(errors: &[FieldError]) -> String {
+    errors
+        .iter()
+        .map(|e| e.message.as_str())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl From<DomainRejection> for SubscribeError {
+    fn from(value: DomainRejection) -> Self {
+        Self::ValidationError(vec![FieldError {
+            field: "email",
+            code: value.reason(),
+            message: value.to_string(),
+        }])
     }
 }
 
@@ -196,10 +296,17 @@ impl std::fmt::Debug for SubscribeError {
 
 impl IntoResponse for SubscribeError {
     fn into_response(self) -> Response {
-        let status_code = match self {
-            SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
-            SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-        };
-        status_code.into_response()
+        match self {
+            SubscribeError::ValidationError(ref errors) => ProblemDetails::new(
+                StatusCode::BAD_REQUEST,
+                "validation-error",
+                "Invalid Subscription",
+            )
+            .with_detail(self.to_string())
+            .with_errors(errors.clone())
+            .into_response(),
+            SubscribeError::TooManyRequests(limited) => limited.into_response(),
+            SubscribeError::UnexpectedError(_) => ProblemDetails::unexpected(&self).into_response(),
+        }
     }
 }
@@ -1,10 +1,15 @@
+use anyhow::Context;
+use axum::extract::rejection::QueryRejection;
 use axum::extract::{Query, State};
-use axum::response::IntoResponse;
-
-use reqwest::StatusCode;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
 use sqlx::{PgPool, Pool, Postgres};
 use uuid::Uuid;
 
+use crate::problem_details::ProblemDetails;
+
+use super::error_chain_format;
+
 #[derive(serde::Deserialize)]
 pub struct Parameters {
     subscription_token: String,
@@ -13,22 +18,17 @@ pub struct Parameters {
 #[tracing::instrument(name = "Confirm a pending subscriber", skip(params))]
 pub async fn confirm(
     State(pool): State<Pool<Postgres>>,
-    Query(params): Query<Parameters>,
-) -> impl IntoResponse {
-    let id = match get_subscriber_id_from_token(&pool, &params.subscription_token).await {
-        Ok(id) => id,
-        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
-    };
-
-    match id {
-        None => StatusCode::INTERNAL_SERVER_ERROR,
-        Some(subscriber_id) => {
-            if confirm_subscriber(&pool, subscriber_id).await.is_err() {
-                return StatusCode::INTERNAL_SERVER_ERROR;
-            }
-            StatusCode::OK
-        }
-    }
+    params: Result<Query<Parameters>, QueryRejection>,
+) -> Result<impl IntoResponse, ConfirmError> {
+    let Query(params) = params?;
+    let subscriber_id = get_subscriber_id_from_token(&pool, &params.subscription_token)
+        .await
+        .context("Failed to retrieve the subscriber id associated with the provided token.")?
+        .ok_or(ConfirmError::UnknownToken)?;
+    confirm_subscriber(&pool, subscriber_id)
+        .await
+        .context("Failed to update the subscriber status to `confirmed`.")?;
+    Ok(StatusCode::OK)
 }
 
 #[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, pool))]
@@ -64,3 +64,41 @@ pub async fn get_subscriber_id_from_token(
     })?;
     Ok(result.map(|r| r.subscriber_id))
 }
+
+#[derive(thiserror::Error)]
+pub enum ConfirmError {
+    #[error(transparent)]
+    InvalidParameters(#[from] QueryRejection),
+    #[error("There is no subscriber associated with the provided token.")]
+    UnknownToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ConfirmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_format(self, f)
+    }
+}
+
+impl IntoResponse for ConfirmError {
+    fn into_response(self) -> Response {
+        match self {
+            ConfirmError::InvalidParameters(rejection) => ProblemDetails::new(
+                rejection.status(),
+                "invalid-parameters",
+                "Invalid Query Parameters",
+            )
+            .with_detail(rejection.body_text())
+            .into_response(),
+            ConfirmError::UnknownToken => ProblemDetails::new(
+                StatusCode::UNAUTHORIZED,
+                "unknown-subscription-token",
+                "Unknown Subscription Token",
+            )
+            .with_detail(self.to_string())
+            .into_response(),
+            ConfirmError::UnexpectedError(_) => ProblemDetails::unexpected(&self).into_response(),
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use async_trait::async_trait;
+use reqwest::Client;
+use secrecy::{ExposeSecret, Secret};
+use url::Url;
+
+use crate::configuration::{SecretsSettings, Settings, VaultSettings};
+use crate::routes::error_chain_format;
+
+/// A store that secret settings can refer to, instead of holding the
+/// secret themselves.
+///
+/// A setting whose whole value is `${<scheme>:<path>#<key>}` is replaced by
+/// the value of `key` in the secret at `path`, fetched from the provider
+/// with that scheme.
+#[async_trait]
+pub trait SecretProvider: Send + Sync {
+    fn scheme(&self) -> &'static str;
+
+    async fn fetch(&self, path: &str, key: &str) -> Result<Secret<String>, anyhow::Error>;
+}
+
+/// The providers enabled in the `secrets` settings.
+pub fn configured_providers(settings: &SecretsSettings) -> Vec<Box<dyn SecretProvider>> {
+    let mut providers: Vec<Box<dyn SecretProvider>> = Vec::new();
+    if let Some(vault) = &settings.vault {
+        providers.push(Box::new(VaultProvider::new(vault)));
+    }
+    providers
+}
+
+/// Replace the secret settings that refer to a provider with the secret
+/// they point to.
+#[tracing::instrument(name = "Resolve secret references", skip_all)]
+pub async fn resolve_secrets(
+    settings: &mut Settings,
+    providers: &[Box<dyn SecretProvider>],
+) -> Result<(), SecretError> {
+    for (setting, secret) in secret_settings(settings) {
+        let Some(reference) = SecretReference::parse(secret.expose_secret()) else {
+            continue;
+        };
+        let reference = reference.map_err(|_| SecretError::InvalidReference(setting))?;
+        let provider = providers
+            .iter()
+            .find(|p| p.scheme() == reference.scheme)
+            .ok_or_else(|| SecretError::UnknownProvider {
+                setting,
+                scheme: reference.scheme.to_owned(),
+            })?;
+        let value = provider
+            .fetch(reference.path, reference.key)
+            .await
+            .map_err(|e| SecretError::FetchFailed(setting, e))?;
+        *secret = value;
+    }
+    Ok(())
+}
+
+pub(crate) fn secret_settings(settings: &mut Settings) -> Vec<(&'static str, &mut Secret<String>)> {
+    let mut secrets = vec![
+        ("database.password", &mut settings.database.password),
+        (
+            "email_client.authorization_token",
+            &mut settings.email_client.authorization_token,
+        ),
+        (
+            "bot_defence.form_secret",
+            &mut settings.bot_defence.form_secret,
+        ),
+        ("csrf.secret", &mut settings.csrf.secret),
+        (
+            "tracing.redaction.salt",
+            &mut settings.tracing.redaction.salt,
+        ),
+    ];
+    if let Some(replica) = &mut settings.read_replica {
+        secrets.push(("read_replica.password", &mut replica.password));
+    }
+    secrets
+}
+
+/// Whether `value` refers to a provider instead of holding the secret.
+pub(crate) fn is_reference(value: &str) -> bool {
+    SecretReference::parse(value).is_some()
+}
+
+struct SecretReference<'a> {
+    scheme: &'a str,
+    path: &'a str,
+    key: &'a str,
+}
+
+impl<'a> SecretReference<'a> {
+    /// `None` for plain values, an error for malformed references.
+    fn parse(value: &'a str) -> Option<Result<Self, ()>> {
+        let inner = value.strip_prefix("${")?.strip_suffix('}')?;
+        let parsed = inner.split_once(':').and_then(|(scheme, rest)| {
+            let (path, key) = rest.split_once('#')?;
+            [scheme, path, key]
+                .iter()
+                .all(|part| !part.is_empty())
+                .then_some(Self { scheme, path, key })
+        });
+        Some(parsed.ok_or(()))
+    }
+}
+
+/// Reads secrets from the KV version 2 engine of HashiCorp Vault, or any
+/// server implementing its HTTP API.
+pub struct VaultProvider {
+    http_client: Client,
+    address: String,
+    token: Secret<String>,
+    mount: String,
+}
+
+impl VaultProvider {
+    pub fn new(settings: &VaultSettings) -> Self {
+        let http_client = Client::builder()
+            .timeout(settings.timeout())
+            .build()
+            .unwrap();
+        Self {
+            http_client,
+            address: settings.address.clone(),
+            token: settings.token.clone(),
+            mount: settings.mount.clone(),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct VaultResponse {
+    data: VaultSecret,
+}
+
+#[derive(serde::Deserialize)]
+struct VaultSecret {
+    data: serde_json::Map<String, serde_json::Value>,
+}
+
+#[async_trait]
+impl SecretProvider for VaultProvider {
+    fn scheme(&self) -> &'static str {
+        "vault"
+    }
+
+    async fn fetch(&self, path: &str, key: &str) -> Result<Secret<String>, anyhow::Error> {
+        let mut url = Url::parse(&self.address)?;
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("The Vault address cannot be a base URL."))?
+            .pop_if_empty()
+            .extend(["v1", &self.mount, "data"])
+            .extend(path.split('/'));
+        let response: VaultResponse = self
+            .http_client
+            .get(url)
+            .header("X-Vault-Token", self.token.expose_secret())
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        match response.data.data.get(key) {
+            Some(serde_json::Value::String(value)) => Ok(Secret::new(value.clone())),
+            Some(_) => anyhow::bail!("`{key}` is not a string in the secret at `{path}`."),
+            None => anyhow::bail!("There is no `{key}` in the secret at `{path}`."),
+        }
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum SecretError {
+    #[error("`{0}` is not a valid secret reference, expected `${{<provider>:<path>#<key>}}`.")]
+    InvalidReference(&'static str),
+    #[error("`{setting}` refers to the `{scheme}` secret provider, which is not configured.")]
+    UnknownProvider {
+        setting: &'static str,
+        scheme: String,
+    },
+    #[error("Failed to fetch the secret referred to by `{0}`.")]
+    FetchFailed(&'static str, #[source] anyhow::Error),
+}
+
+impl std::fmt::Debug for SecretError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_format(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::get_configuration;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn vault() -> (MockServer, Vec<Box<dyn SecretProvider>>) {
+        let server = MockServer::start().await;
+        let provider = VaultProvider::new(&VaultSettings {
+            address: server.uri(),
+            token: Secret::new("vault-token".into()),
+            mount: "secret".into(),
+            timeout_milliseconds: 200,
+        });
+        (server, vec![Box::new(provider)])
+    }
+
+    #[tokio::test]
+    async fn references_are_replaced_by_the_secrets_stored_in_vault() {
+        let (server, providers) = vault().await;
+        Mock::given(method("GET"))
+            .and(path("/v1/secret/data/zero2prod/postmark"))
+            .and(header("X-Vault-Token", "vault-token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": { "data": { "token": "postmark-token" }, "metadata": {} }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let mut settings = get_configuration().unwrap();
+        settings.email_client.authorization_token =
+            Secret::new("${vault:zero2prod/postmark#token}".into());
+        let form_secret = settings.bot_defence.form_secret.expose_secret().clone();
+
+        resolve_secrets(&mut settings, &providers).await.unwrap();
+
+        assert_eq!(
+            settings.email_client.authorization_token.expose_secret(),
+            "postmark-token"
+        );
+        assert_eq!(
+            settings.bot_defence.form_secret.expose_secret(),
+            &form_secret
+        );
+    }
+
+    #[tokio::test]
+    async fn a_key_missing_from_the_secret_is_an_error() {
+        let (server, providers) = vault().await;
+        Mock::given(path("/v1/secret/data/zero2prod/postmark"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": { "data": { "other": "value" } }
+            })))
+            .mount(&server)
+            .await;
+        let mut settings = get_configuration().unwrap();
+        settings.database.password = Secret::new("${vault:zero2prod/postmark#token}".into());
+
+        let error = resolve_secrets(&mut settings, &providers)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(
+            error,
+            SecretError::FetchFailed("database.password", _)
+        ));
+    }
+
+    #[tokio::test]
+    async fn references_to_providers_that_are_not_configured_are_an_error() {
+        let mut settings = get_configuration().unwrap();
+        settings.database.password = Secret::new("${vault:zero2prod/db#password}".into());
+
+        let error = resolve_secrets(&mut settings, &[]).await.unwrap_err();
+
+        assert!(matches!(error, SecretError::UnknownProvider { .. }));
+    }
+
+    #[test]
+    fn only_values_wrapped_in_braces_are_references() {
+        assert!(SecretReference::parse("vault:a#b").is_none());
+        assert!(SecretReference::parse("plain-secret").is_none());
+        assert!(SecretReference::parse("${vault:a}").unwrap().is_err());
+        assert!(SecretReference::parse("${:a#b}").unwrap().is_err());
+
+        let reference = SecretReference::parse("${vault:a/b#c}").unwrap().unwrap();
+        assert_eq!(
+            (reference.scheme, reference.path, reference.key),
+            ("vault", "a/b", "c")
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use axum::extract::{ConnectInfo, Request};
+use axum::Router;
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use hyper_util::server::graceful::{GracefulShutdown, Watcher};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::TlsAcceptor;
+use tower_service::Service;
+
+use crate::startup::Listener;
+
+/// Clients get this long to complete the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serves a router over HTTP/1.1 and HTTP/2, in plain text or over TLS.
+///
+/// Over TLS the protocol is negotiated through ALPN; in plain text HTTP/2
+/// is only spoken to clients that start with its preface.
+pub struct Server {
+    listener: Listener,
+    app: Router,
+    tls: Option<TlsAcceptor>,
+}
+
+impl Server {
+    pub fn new(listener: Listener, app: Router, tls: Option<TlsAcceptor>) -> Self {
+        Self { listener, app, tls }
+    }
+
+    /// Accept connections until `stop` resolves, then wait for the open
+    /// ones to finish their in-flight requests.
+    pub async fn run(self, stop: impl Future<Output = ()>) -> Result<(), std::io::Error> {
+        let Self { listener, app, tls } = self;
+        let builder = auto::Builder::new(TokioExecutor::new());
+        let graceful = GracefulShutdown::new();
+        tokio::pin!(stop);
+
+        loop {
+            let (stream, remote_addr) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        // Usually running out of file descriptors, give
+                        // other connections a chance to close.
+                        tracing::error!(error.cause_chain = ?e, "Failed to accept a connection");
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                },
+                _ = &mut stop => break,
+            };
+            let connection = Connection {
+                builder: builder.clone(),
+                watcher: graceful.watcher(),
+                app: app.clone(),
+                remote_addr,
+            };
+            match tls.clone() {
+                Some(acceptor) => tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => connection.serve(stream).await,
+                        Ok(Err(e)) => tracing::debug!(error = %e, "TLS handshake failed"),
+                        Err(_) => tracing::debug!("TLS handshake timed out"),
+                    }
+                }),
+                None => tokio::spawn(connection.serve(stream)),
+            };
+        }
+
+        drop(listener);
+        graceful.shutdown().await;
+        Ok(())
+    }
+}
+
+struct Connection {
+    builder: auto::Builder<TokioExecutor>,
+    watcher: Watcher,
+    app: Router,
+    remote_addr: SocketAddr,
+}
+
+impl Connection {
+    async fn serve<S>(self, stream: S)
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let Self {
+            builder,
+            watcher,
+            app,
+            remote_addr,
+        } = self;
+        let service = hyper::service::service_fn(move |mut request: Request<Incoming>| {
+            request.extensions_mut().insert(ConnectInfo(remote_addr));
+            app.clone().call(request)
+        });
+        let connection = builder.serve_connection(TokioIo::new(stream), service);
+        if let Err(e) = watcher.watch(connection).await {
+            tracing::debug!(error = %e, "Failed to serve a connection");
+        }
+    }
+}
@@ -1,24 +1,65 @@
+use arc_swap::ArcSwap;
 use axum::{
     body::Body,
-    extract::{FromRef, Request},
-    routing::{get, post},
-    serve::Serve,
+    extract::{DefaultBodyLimit, FromRef, Request},
+    http::Uri,
+    middleware,
+    response::Redirect,
+    routing::{get, post, put},
     Router,
 };
-use sqlx::{postgres::PgPoolOptions, PgPool, Pool, Postgres};
-use tokio::net::TcpListener;
+use sqlx::{pool::PoolConnection, postgres::PgPoolOptions, PgPool, Pool, Postgres};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tokio_rustls::TlsAcceptor;
+#[cfg(unix)]
+use tokio_util::either::Either;
+use tower_http::limit::RequestBodyLimitLayer;
+use tower_http::timeout::TimeoutLayer;
 use tower_http::trace::TraceLayer;
-use uuid::Uuid;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 use crate::{
-    configuration::{DatabaseSettings, Settings},
+    blocked_domains::{refresh_blocked_domains, stored_blocked_domains},
+    bot_defence::BotDefence,
+    configuration::{
+        ApplicationSettings, DatabaseSettings, HealthSettings, HttpSettings, ListenerSettings,
+        Settings,
+    },
+    csrf::{check_admin_token, check_origin, Csrf},
+    domain::DomainPolicy,
     email_client::EmailClient,
-    routes::{confirm, health_check, publish_newsletter, subscribe},
+    hardening::{self, add_security_headers, SecurityHeaders},
+    migration::migrate_database,
+    problem_details::not_found,
+    prometheus::{admin_metrics_endpoint, metrics_endpoint, track_http_metrics, Metrics},
+    rate_limit::{limit_by_client_ip, sweep_expired_windows, RateLimiter},
+    reload::{reload_on_change, Reloader},
+    request_id::{assign_request_id, RequestId},
+    routes::{
+        block_domain, confirm, get_log_level, health_check, issue_csrf_token, issue_form_token,
+        list_blocked_domains, list_subscribers, liveness, publish_newsletter, readiness,
+        set_log_level, subscribe, unblock_domain, upload_subscribers, LogLevelOverride,
+    },
+    server::Server,
+    shutdown::{termination_signal, Shutdown},
+    telemetry::extract_trace_context,
+    tls::{self, ReloadingCertificate},
 };
 
 pub struct Application {
     port: u16,
-    server: Serve<Router, Router>,
+    metrics_port: Option<u16>,
+    redirect_port: Option<u16>,
+    server: Server,
+    shutdown: Shutdown,
+    shutdown_timeout: Duration,
+    reloader: Reloader,
 }
 
 #[derive(Clone)]
@@ -27,8 +68,18 @@ pub struct ApplicationBaseUrl(pub String);
 #[derive(Clone)]
 pub struct ApplicationState {
     pub db_connection: Pool<Postgres>,
+    pub read_pool: ReadPool,
     pub email_client: EmailClient,
     pub base_url: ApplicationBaseUrl,
+    pub domain_policy: Arc<DomainPolicy>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub bot_defence: Arc<BotDefence>,
+    pub csrf: Arc<Csrf>,
+    pub metrics: Arc<Metrics>,
+    pub log_level: Arc<LogLevelOverride>,
+    /// The current settings, swapped by [`Reloader`].
+    pub settings: Arc<ArcSwap<Settings>>,
+    pub shutdown: Shutdown,
 }
 
 impl FromRef<ApplicationState> for ApplicationBaseUrl {
@@ -43,89 +94,618 @@ impl FromRef<ApplicationState> for Pool<Postgres> {
     }
 }
 
+impl FromRef<ApplicationState> for ReadPool {
+    fn from_ref(input: &ApplicationState) -> Self {
+        input.read_pool.clone()
+    }
+}
+
 impl FromRef<ApplicationState> for EmailClient {
     fn from_ref(input: &ApplicationState) -> Self {
         input.email_client.clone()
     }
 }
 
+impl FromRef<ApplicationState> for Arc<DomainPolicy> {
+    fn from_ref(input: &ApplicationState) -> Self {
+        input.domain_policy.clone()
+    }
+}
+
+impl FromRef<ApplicationState> for Arc<RateLimiter> {
+    fn from_ref(input: &ApplicationState) -> Self {
+        input.rate_limiter.clone()
+    }
+}
+
+impl FromRef<ApplicationState> for Arc<BotDefence> {
+    fn from_ref(input: &ApplicationState) -> Self {
+        input.bot_defence.clone()
+    }
+}
+
+impl FromRef<ApplicationState> for Arc<Csrf> {
+    fn from_ref(input: &ApplicationState) -> Self {
+        input.csrf.clone()
+    }
+}
+
+impl FromRef<ApplicationState> for HealthSettings {
+    fn from_ref(input: &ApplicationState) -> Self {
+        input.settings.load().health.clone()
+    }
+}
+
+impl FromRef<ApplicationState> for Arc<Metrics> {
+    fn from_ref(input: &ApplicationState) -> Self {
+        input.metrics.clone()
+    }
+}
+
+impl FromRef<ApplicationState> for Arc<LogLevelOverride> {
+    fn from_ref(input: &ApplicationState) -> Self {
+        input.log_level.clone()
+    }
+}
+
+impl FromRef<ApplicationState> for Shutdown {
+    fn from_ref(input: &ApplicationState) -> Self {
+        input.shutdown.clone()
+    }
+}
+
+/// Where the application accepts connections, see [`ListenerSettings`].
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+#[cfg(unix)]
+pub type Stream = Either<TcpStream, UnixStream>;
+#[cfg(not(unix))]
+pub type Stream = TcpStream;
+
+/// The first descriptor passed by systemd.
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: std::os::fd::RawFd = 3;
+
+impl Listener {
+    pub async fn bind(settings: &ApplicationSettings) -> Result<Self, std::io::Error> {
+        match &settings.listener {
+            ListenerSettings::Tcp => {
+                let address = format!("{}:{}", settings.host, settings.port);
+                Ok(Self::Tcp(TcpListener::bind(address).await?))
+            }
+            #[cfg(unix)]
+            ListenerSettings::Unix { path, .. } => {
+                let mode = settings
+                    .listener
+                    .unix_mode()
+                    .map_err(std::io::Error::other)?;
+                Self::bind_unix(path, mode)
+            }
+            #[cfg(unix)]
+            ListenerSettings::Systemd => Self::inherit(
+                std::env::var("LISTEN_PID").ok().as_deref(),
+                std::env::var("LISTEN_FDS").ok().as_deref(),
+            ),
+            #[cfg(not(unix))]
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "Only TCP listeners are supported on this platform.",
+            )),
+        }
+    }
+
+    #[cfg(unix)]
+    fn bind_unix(path: &Path, mode: Option<u32>) -> Result<Self, std::io::Error> {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+        // Left behind by a previous run that did not stop cleanly.
+        if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        if let Some(mode) = mode {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+        }
+        Ok(Self::Unix(listener, path.to_owned()))
+    }
+
+    /// Take over the socket systemd passed, given the values of the
+    /// `LISTEN_PID` and `LISTEN_FDS` environment variables.
+    #[cfg(unix)]
+    fn inherit(pid: Option<&str>, fds: Option<&str>) -> Result<Self, std::io::Error> {
+        use std::os::fd::{FromRawFd, OwnedFd};
+
+        let for_us = pid.and_then(|pid| pid.parse().ok()) == Some(std::process::id());
+        let count: u32 = fds.and_then(|fds| fds.parse().ok()).unwrap_or_default();
+        if !for_us || count == 0 {
+            return Err(std::io::Error::other(
+                "No socket was passed by systemd, check LISTEN_PID and LISTEN_FDS.",
+            ));
+        }
+        if count > 1 {
+            tracing::warn!(count, "Only the first socket passed by systemd is used");
+        }
+        // SAFETY: systemd passes open sockets from `SD_LISTEN_FDS_START`
+        // on, and nothing else in the process takes ownership of them.
+        let fd = unsafe { OwnedFd::from_raw_fd(SD_LISTEN_FDS_START) };
+        // `local_addr` fails on sockets that are not Unix ones.
+        let unix = std::os::unix::net::UnixListener::from(fd);
+        if let Ok(address) = unix.local_addr() {
+            unix.set_nonblocking(true)?;
+            tracing::info!(?address, "Listening on a Unix socket passed by systemd");
+            // No path: removing an inherited socket is systemd's job.
+            return Ok(Self::Unix(UnixListener::from_std(unix)?, PathBuf::new()));
+        }
+        let tcp = std::net::TcpListener::from(OwnedFd::from(unix));
+        tcp.set_nonblocking(true)?;
+        Ok(Self::Tcp(TcpListener::from_std(tcp)?))
+    }
+
+    /// The TCP port, if listening on one.
+    pub fn port(&self) -> Option<u16> {
+        match self {
+            Self::Tcp(listener) => listener.local_addr().ok().map(|a| a.port()),
+            #[cfg(unix)]
+            Self::Unix(..) => None,
+        }
+    }
+
+    pub fn is_unix(&self) -> bool {
+        match self {
+            Self::Tcp(_) => false,
+            #[cfg(unix)]
+            Self::Unix(..) => true,
+        }
+    }
+
+    /// Accept a connection, with the address of the peer.
+    pub async fn accept(&self) -> Result<(Stream, SocketAddr), std::io::Error> {
+        match self {
+            Self::Tcp(listener) => {
+                let (stream, peer) = listener.accept().await?;
+                let _ = stream.set_nodelay(true);
+                #[cfg(unix)]
+                let stream = Either::Left(stream);
+                Ok((stream, peer))
+            }
+            #[cfg(unix)]
+            Self::Unix(listener, _) => {
+                let (stream, _) = listener.accept().await?;
+                // Peers are on this host, whatever process they are. The
+                // rate limiter takes them for reverse proxies.
+                let peer = SocketAddr::from((std::net::Ipv4Addr::LOCALHOST, 0));
+                Ok((Either::Right(stream), peer))
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Self::Unix(_, path) = self {
+            if !path.as_os_str().is_empty() {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+}
+
 pub fn get_connection_pool(confguration: &DatabaseSettings) -> PgPool {
-    PgPoolOptions::new().connect_lazy_with(confguration.with_db())
+    PgPoolOptions::new()
+        .max_connections(confguration.max_connections)
+        .acquire_timeout(confguration.acquire_timeout())
+        .connect_lazy_with(confguration.pooled())
+}
+
+/// Where read-only queries go: the read replica if there is one, the
+/// primary otherwise.
+#[derive(Clone)]
+pub struct ReadPool {
+    replica: Option<PgPool>,
+    primary: PgPool,
+    /// Until when reads skip a replica that could not be reached.
+    replica_down_until: Arc<std::sync::Mutex<Option<Instant>>>,
+}
+
+/// How long a read waits for a replica connection before using the primary.
+const REPLICA_PROBE_TIMEOUT: Duration = Duration::from_millis(250);
+/// How long reads go to the primary after the replica could not be reached.
+const REPLICA_BACK_OFF: Duration = Duration::from_secs(30);
+
+impl ReadPool {
+    pub fn new(replica: Option<&DatabaseSettings>, primary: PgPool) -> Self {
+        Self {
+            replica: replica.map(get_connection_pool),
+            primary,
+            replica_down_until: Default::default(),
+        }
+    }
+
+    /// A connection to the replica, or to the primary while the replica
+    /// cannot be reached.
+    pub async fn acquire(&self) -> Result<PoolConnection<Postgres>, sqlx::Error> {
+        if let Some(replica) = self.replica.as_ref().filter(|_| !self.replica_is_down()) {
+            let error = match tokio::time::timeout(REPLICA_PROBE_TIMEOUT, replica.acquire()).await {
+                Ok(Ok(connection)) => return Ok(connection),
+                Ok(Err(e)) => e,
+                Err(_) => sqlx::Error::PoolTimedOut,
+            };
+            *self.replica_down_until.lock().unwrap() = Some(Instant::now() + REPLICA_BACK_OFF);
+            tracing::warn!(
+                error.cause_chain = ?error,
+                "The read replica is unavailable, reading from the primary for {:?}",
+                REPLICA_BACK_OFF
+            );
+        }
+        if self.replica.is_some() {
+            metrics::counter!("db_read_replica_fallbacks_total").increment(1);
+        }
+        self.primary.acquire().await
+    }
+
+    fn replica_is_down(&self) -> bool {
+        self.replica_down_until
+            .lock()
+            .unwrap()
+            .is_some_and(|until| Instant::now() < until)
+    }
 }
 
 impl Application {
     pub async fn build(configuration: Settings) -> Result<Self, std::io::Error> {
+        migrate_database(
+            &configuration.database,
+            configuration.application.run_migrations,
+        )
+        .await
+        .map_err(std::io::Error::other)?;
+
+        let settings = Arc::new(ArcSwap::from_pointee(configuration.clone()));
         let connection_pool = get_connection_pool(&configuration.database);
+        let listener = Listener::bind(&configuration.application).await?;
+        let port = listener.port().unwrap_or_default();
+        let rate_limiter = Arc::new(RateLimiter::new(
+            &configuration.rate_limit,
+            connection_pool.clone(),
+            listener.is_unix() && configuration.rate_limit.trust_unix_socket_peers,
+        ));
 
-        let sender_email = configuration
-            .email_client
-            .sender()
-            .expect("Invalid sender email address");
-        let timeout = configuration.email_client.timeout();
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url,
-            sender_email,
-            configuration.email_client.authorization_token,
-            timeout,
-        );
+        let shutdown = Shutdown::new();
+        shutdown.spawn(sweep_expired_windows(
+            rate_limiter.clone(),
+            shutdown.clone(),
+        ));
 
-        let address = format!(
-            "{}:{}",
-            configuration.application.host, configuration.application.port
-        );
-        let listener = TcpListener::bind(address).await?;
-        let port = listener.local_addr().unwrap().port();
+        let read_pool = ReadPool::new(configuration.read_replica.as_ref(), connection_pool.clone());
+        let metrics = Arc::new(Metrics::new(connection_pool.clone(), read_pool.clone()));
+        let metrics_port = match configuration.metrics.port {
+            Some(port) => Some(
+                serve_metrics(
+                    &configuration.metrics.host,
+                    port,
+                    metrics.clone(),
+                    &shutdown,
+                )
+                .await?,
+            ),
+            None => None,
+        };
+
+        let tls = match configuration.application.tls.clone() {
+            Some(settings) => {
+                let certificate =
+                    Arc::new(ReloadingCertificate::new(settings).map_err(std::io::Error::other)?);
+                shutdown.spawn(tls::reload_on_change(certificate.clone(), shutdown.clone()));
+                Some(certificate.acceptor().map_err(std::io::Error::other)?)
+            }
+            None => None,
+        };
+        let redirect_port = match configuration
+            .application
+            .tls
+            .as_ref()
+            .and_then(|tls| tls.redirect_port)
+        {
+            Some(port) => Some(
+                serve_redirect(
+                    &configuration.application.host,
+                    port,
+                    configuration.application.link_base().to_owned(),
+                    &shutdown,
+                )
+                .await?,
+            ),
+            None => None,
+        };
+
+        let email_client = configuration.email_client.client();
+        let reloader = Reloader::new(settings.clone(), email_client.clone(), rate_limiter.clone());
+        shutdown.spawn(reload_on_change(
+            reloader.clone(),
+            configuration.application.watch_configuration,
+            shutdown.clone(),
+        ));
 
+        let domain_policy = Arc::new(configuration.domain_policy.policy());
+        // The application starts without them if the database is down.
+        match stored_blocked_domains(&connection_pool).await {
+            Ok(blocked) => domain_policy.set_blocked(&blocked),
+            Err(e) => tracing::warn!(error.cause_chain = ?e, "Failed to load the blocked domains"),
+        }
+        shutdown.spawn(refresh_blocked_domains(
+            domain_policy.clone(),
+            connection_pool.clone(),
+            shutdown.clone(),
+        ));
+
+        let state = ApplicationState {
+            db_connection: connection_pool,
+            read_pool,
+            email_client,
+            base_url: ApplicationBaseUrl(configuration.application.link_base().to_owned()),
+            domain_policy,
+            rate_limiter,
+            bot_defence: Arc::new(BotDefence::new(configuration.bot_defence)),
+            csrf: Arc::new(Csrf::new(
+                configuration.csrf.clone(),
+                configuration.application.base_url.as_str(),
+                &configuration.application.http.cors.allowed_origins,
+            )),
+            metrics,
+            log_level: Arc::new(LogLevelOverride::new(settings.clone())),
+            settings,
+            shutdown: shutdown.clone(),
+        };
         let server = run(
             listener,
-            connection_pool,
-            email_client,
-            configuration.application.base_url,
+            state,
+            &configuration.application.http,
+            metrics_port.is_none(),
+            tls,
         )?;
 
-        Ok(Self { port, server })
+        Ok(Self {
+            port,
+            metrics_port,
+            redirect_port,
+            server,
+            shutdown,
+            shutdown_timeout: configuration.application.shutdown_timeout(),
+            reloader,
+        })
     }
 
+    /// The TCP port requests are served on, `0` when listening on something
+    /// else.
     pub fn port(&self) -> u16 {
         self.port
     }
 
+    /// The port serving `/metrics`, if it is not the application port.
+    pub fn metrics_port(&self) -> Option<u16> {
+        self.metrics_port
+    }
+
+    /// The plain HTTP port redirecting to HTTPS, if any.
+    pub fn redirect_port(&self) -> Option<u16> {
+        self.redirect_port
+    }
+
+    /// Handle to stop the application without sending it a signal.
+    pub fn shutdown(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    /// Handle to reload settings without sending SIGHUP.
+    pub fn reloader(&self) -> Reloader {
+        self.reloader.clone()
+    }
+
+    /// Serve until SIGINT, SIGTERM or [`Shutdown::trigger`], then stop
+    /// accepting connections and give in-flight requests and background
+    /// workers up to `shutdown_timeout` to finish.
     pub async fn run_untill_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+        let Self {
+            server,
+            shutdown,
+            shutdown_timeout,
+            ..
+        } = self;
+
+        let on_signal = shutdown.clone();
+        let signal_listener = tokio::spawn(async move {
+            let signal = termination_signal().await;
+            tracing::info!(signal, "Received a termination signal");
+            on_signal.trigger();
+        });
+
+        let stopping = shutdown.clone();
+        let server = server.run(async move { stopping.cancelled().await });
+        tokio::pin!(server);
+        tokio::select! {
+            result = &mut server => {
+                signal_listener.abort();
+                return result;
+            }
+            _ = shutdown.cancelled() => {}
+        }
+        signal_listener.abort();
+
+        tracing::info!(
+            timeout_seconds = shutdown_timeout.as_secs(),
+            "Shutting down, draining in-flight requests"
+        );
+        let deadline = tokio::time::Instant::now() + shutdown_timeout;
+        match tokio::time::timeout_at(deadline, &mut server).await {
+            Ok(result) => result?,
+            Err(_) => tracing::warn!("Timed out draining requests, dropping open connections"),
+        }
+
+        let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+        if shutdown.wait_for_workers(remaining).await {
+            tracing::info!("Shutdown complete");
+        } else {
+            tracing::warn!(
+                workers = shutdown.running_workers(),
+                "Timed out waiting for background workers"
+            );
+        }
+        Ok(())
     }
 }
 
-pub fn run(
-    listener: TcpListener,
-    connection: PgPool,
-    email_client: EmailClient,
+/// Redirect every request to the same path under `base_url` until
+/// shutdown. Returns the bound port.
+async fn serve_redirect(
+    host: &str,
+    port: u16,
     base_url: String,
-) -> Result<Serve<Router, Router>, std::io::Error> {
+    shutdown: &Shutdown,
+) -> Result<u16, std::io::Error> {
+    let listener = TcpListener::bind(format!("{}:{}", host, port)).await?;
+    let port = listener.local_addr()?.port();
+    let app = Router::new().fallback(move |uri: Uri| async move {
+        let path = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+        Redirect::permanent(&format!("{}{}", base_url, path))
+    });
+    let stopping = shutdown.clone();
+    shutdown.spawn(async move {
+        let server = axum::serve(listener, app)
+            .with_graceful_shutdown(async move { stopping.cancelled().await });
+        if let Err(e) = server.await {
+            tracing::error!(error.cause_chain = ?e, "The HTTPS redirect server failed");
+        }
+    });
+    Ok(port)
+}
+
+/// Serve `/metrics` on its own port until shutdown. Returns the bound port.
+async fn serve_metrics(
+    host: &str,
+    port: u16,
+    metrics: Arc<Metrics>,
+    shutdown: &Shutdown,
+) -> Result<u16, std::io::Error> {
+    let listener = TcpListener::bind(format!("{}:{}", host, port)).await?;
+    let port = listener.local_addr()?.port();
     let app = Router::new()
-        .route("/health_check", get(health_check))
+        .route("/metrics", get(metrics_endpoint))
+        .with_state(metrics);
+    let stopping = shutdown.clone();
+    shutdown.spawn(async move {
+        let server = axum::serve(listener, app)
+            .with_graceful_shutdown(async move { stopping.cancelled().await });
+        if let Err(e) = server.await {
+            tracing::error!(error.cause_chain = ?e, "The metrics server failed");
+        }
+    });
+    Ok(port)
+}
+
+pub fn run(
+    listener: Listener,
+    state: ApplicationState,
+    http: &HttpSettings,
+    serve_metrics: bool,
+    tls: Option<TlsAcceptor>,
+) -> Result<Server, std::io::Error> {
+    // Endpoints that trigger emails to arbitrary addresses are throttled.
+    let mut public_routes = Router::new()
         .route("/subscriptions", post(subscribe))
         .route("/subscriptions/confirm", get(confirm))
-        .route("/newsletters", post(publish_newsletter))
+        .route("/subscriptions/form_token", get(issue_form_token))
+        .route_layer(middleware::from_fn_with_state(
+            state.rate_limiter.clone(),
+            limit_by_client_ip,
+        ))
+        // Forged posts are refused before they count against the limit.
+        .route_layer(middleware::from_fn_with_state(
+            state.csrf.clone(),
+            check_origin,
+        ));
+    // Preflight requests are answered before they count against the limit.
+    if let Some(cors) = hardening::cors(&http.cors).map_err(std::io::Error::other)? {
+        public_routes = public_routes.layer(cors);
+    }
+
+    let admin_csrf = middleware::from_fn_with_state(state.csrf.clone(), check_admin_token);
+    let admin_routes = Router::new()
+        .route("/admin/csrf-token", get(issue_csrf_token))
+        .route("/admin/subscribers", get(list_subscribers))
+        .route("/admin/blocked_domains", get(list_blocked_domains))
+        .route(
+            "/admin/blocked_domains/:domain",
+            put(block_domain).delete(unblock_domain),
+        )
+        .route("/admin/log-level", get(get_log_level).put(set_log_level))
+        .route_layer(admin_csrf.clone());
+
+    let mut app = Router::new()
+        .route("/health_check", get(health_check))
+        .route("/health/live", get(liveness))
+        .route("/health/ready", get(readiness))
+        .merge(public_routes)
+        .merge(admin_routes);
+    if serve_metrics {
+        app = app.route("/metrics", get(admin_metrics_endpoint));
+    }
+    let mut app = app
+        .layer(TimeoutLayer::new(http.request_timeout()))
+        // Delivery runs within the request, a timeout would leave it half done.
+        .route(
+            "/newsletters",
+            post(publish_newsletter).route_layer(admin_csrf.clone()),
+        )
+        .layer(RequestBodyLimitLayer::new(http.max_body_bytes))
+        .layer(DefaultBodyLimit::max(http.max_body_bytes))
+        // Added after the limits above, which would cut large imports short.
+        .route(
+            "/admin/subscribers/import",
+            post(upload_subscribers)
+                .route_layer(admin_csrf)
+                .route_layer(TimeoutLayer::new(http.import_timeout()))
+                .route_layer(RequestBodyLimitLayer::new(http.max_import_bytes)),
+        )
+        .route_layer(middleware::from_fn(track_http_metrics))
+        .fallback(not_found);
+    let security_headers =
+        SecurityHeaders::new(&http.security_headers).map_err(std::io::Error::other)?;
+    app = app.layer(middleware::from_fn_with_state(
+        Arc::new(security_headers),
+        add_security_headers,
+    ));
+    if let Some(compression) = hardening::compression(&http.compression) {
+        app = app.layer(compression);
+    }
+    let app = app
         .layer(
             TraceLayer::new_for_http().make_span_with(|request: &Request<Body>| {
-                let request_id = Uuid::new_v4();
-                tracing::span!(
+                // Picks up the caller's trace, if it sent a `traceparent`.
+                let request_id = request
+                    .extensions()
+                    .get::<RequestId>()
+                    .map(ToString::to_string)
+                    .unwrap_or_default();
+                let span = tracing::span!(
                     tracing::Level::INFO,
                     "request",
                     method = tracing::field::display(request.method()),
-                    uri = tracing::field::display(request.uri()),
+                    // The query string may hold personal data or tokens.
+                    uri = tracing::field::display(request.uri().path()),
                     version = tracing::field::debug(request.version()),
                     request_id = tracing::field::display(request_id)
-                )
+                );
+                span.set_parent(extract_trace_context(request.headers()));
+                span
             }),
         )
-        .with_state(ApplicationState {
-            db_connection: connection,
-            email_client,
-            base_url: ApplicationBaseUrl(base_url),
-        });
-
-    let server = axum::serve(listener, app);
+        .layer(middleware::from_fn(assign_request_id))
+        .with_state(state);
 
-    Ok(server)
+    Ok(Server::new(listener, app, tls))
 }
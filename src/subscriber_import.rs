@@ -0,0 +1,425 @@
+use chrono::Utc;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Transaction};
+use tokio::io::AsyncRead;
+use tokio::sync::mpsc;
+use tokio_util::io::SyncIoBridge;
+use uuid::Uuid;
+
+use crate::domain::{DomainPolicy, NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::email_client::EmailClient;
+use crate::routes::{
+    error_chain_format, generate_subscriptions_token, send_confirmation_email, store_token,
+};
+use crate::startup::ApplicationBaseUrl;
+
+/// Parsed rows buffered between the CSV reader and the database writer.
+const ROW_BUFFER: usize = 1024;
+/// Confirmation emails sent at the same time.
+const CONCURRENT_EMAILS: usize = 10;
+
+/// Status given to every imported subscriber.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportStatus {
+    /// Subscribers that already opted in with the previous provider.
+    #[default]
+    Confirmed,
+    PendingConfirmation,
+}
+
+impl ImportStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ImportStatus::Confirmed => "confirmed",
+            ImportStatus::PendingConfirmation => "pending_confirmation",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    pub status: ImportStatus,
+    /// Only valid for [`ImportStatus::PendingConfirmation`].
+    pub send_confirmation: bool,
+    /// Rows written per transaction.
+    pub batch_size: usize,
+}
+
+impl ImportOptions {
+    pub fn new(status: ImportStatus, send_confirmation: bool) -> Result<Self, ImportError> {
+        if send_confirmation && status != ImportStatus::PendingConfirmation {
+            return Err(ImportError::InvalidOptions(
+                "Confirmation emails can only be sent to pending subscribers.",
+            ));
+        }
+        Ok(Self {
+            status,
+            send_confirmation,
+            batch_size: 500,
+        })
+    }
+}
+
+/// Outcome of an import, row by row for everything that was not imported.
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    pub imported: u64,
+    pub duplicates: Vec<DuplicateRow>,
+    pub errors: Vec<RowError>,
+    /// Why the input stopped before its end; the rows read until then
+    /// were imported.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DuplicateRow {
+    pub line: u64,
+    pub email: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RowError {
+    pub line: u64,
+    /// Machine-readable cause, e.g. `invalid_email`.
+    pub code: &'static str,
+    pub message: String,
+}
+
+#[derive(Deserialize)]
+struct CsvRow {
+    email: String,
+    name: String,
+}
+
+struct ParsedRow {
+    line: u64,
+    row: Result<NewSubscriber, RowError>,
+}
+
+impl ParsedRow {
+    fn parse(line: u64, record: Result<CsvRow, csv::Error>) -> Self {
+        let error = |code, message| RowError {
+            line,
+            code,
+            message,
+        };
+        let row = record
+            .map_err(|e| error("malformed_row", e.to_string()))
+            .and_then(|r| {
+                let name = SubscriberName::parse(r.name).map_err(|m| error("invalid_name", m))?;
+                let email =
+                    SubscriberEmail::parse(r.email).map_err(|m| error("invalid_email", m))?;
+                Ok(NewSubscriber { email, name })
+            });
+        Self { line, row }
+    }
+}
+
+/// Import subscribers from CSV with `email` and `name` columns.
+///
+/// Rows are parsed as they are read, so the input is never held in memory.
+/// Invalid rows, addresses refused by `domain_policy` and addresses that
+/// are already subscribed are reported and skipped; everything else is written in batches of
+/// `options.batch_size`, each in its own transaction. If the input fails
+/// halfway, the rows read until then are still written and the report says
+/// why it stopped.
+#[tracing::instrument(
+    name = "Import subscribers",
+    skip(input, pool, domain_policy, email_client, base_url),
+    fields(imported = tracing::field::Empty)
+)]
+pub async fn import_subscribers<R>(
+    input: R,
+    pool: &PgPool,
+    domain_policy: &DomainPolicy,
+    email_client: &EmailClient,
+    base_url: &ApplicationBaseUrl,
+    options: &ImportOptions,
+) -> Result<ImportReport, ImportError>
+where
+    R: AsyncRead + Send + Unpin + 'static,
+{
+    let (sender, mut receiver) = mpsc::channel(ROW_BUFFER);
+    let reader = tokio::task::spawn_blocking(move || read_rows(SyncIoBridge::new(input), sender));
+
+    let mut report = ImportReport::default();
+    let mut batch = Vec::with_capacity(options.batch_size);
+    while let Some(parsed) = receiver.recv().await {
+        let row = parsed.row.and_then(|subscriber| {
+            domain_policy
+                .check(&subscriber.email)
+                .map_err(|rejection| RowError {
+                    line: parsed.line,
+                    code: rejection.reason(),
+                    message: rejection.to_string(),
+                })?;
+            Ok(subscriber)
+        });
+        match row {
+            Ok(subscriber) => batch.push((parsed.line, subscriber)),
+            Err(error) => report.errors.push(error),
+        }
+        if batch.len() >= options.batch_size {
+            write_batch(
+                pool,
+                email_client,
+                base_url,
+                options,
+                &mut batch,
+                &mut report,
+            )
+            .await;
+        }
+    }
+    write_batch(
+        pool,
+        email_client,
+        base_url,
+        options,
+        &mut batch,
+        &mut report,
+    )
+    .await;
+    if let Some(e) = reader
+        .await
+        .map_err(|e| ImportError::UnexpectedError(e.into()))??
+    {
+        tracing::warn!(error.cause_chain = ?e, "The CSV input stopped before its end");
+        report.read_error = Some(e.to_string());
+    }
+
+    tracing::Span::current().record("imported", report.imported);
+    Ok(report)
+}
+
+/// Runs on a blocking thread, feeding parsed rows to the async writer.
+///
+/// Returns the error that stopped the input after the header, if any: the
+/// rows sent until then are still imported.
+fn read_rows(
+    input: impl std::io::Read,
+    sender: mpsc::Sender<ParsedRow>,
+) -> Result<Option<csv::Error>, ImportError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(input);
+    let headers = reader.headers().map_err(ImportError::ReadError)?.clone();
+    if !["email", "name"]
+        .iter()
+        .all(|column| headers.iter().any(|h| h == *column))
+    {
+        return Err(ImportError::MissingColumns);
+    }
+
+    let mut record = csv::StringRecord::new();
+    loop {
+        match reader.read_record(&mut record) {
+            Ok(true) => {}
+            Ok(false) => return Ok(None),
+            Err(e) if e.is_io_error() => return Ok(Some(e)),
+            // Parse errors are confined to the offending row.
+            Err(e) => {
+                let line = e.position().map_or(0, |p| p.line());
+                if sender
+                    .blocking_send(ParsedRow::parse(line, Err(e)))
+                    .is_err()
+                {
+                    return Ok(None);
+                }
+                continue;
+            }
+        }
+        let line = record.position().map_or(0, |p| p.line());
+        let parsed = ParsedRow::parse(line, record.deserialize(Some(&headers)));
+        if sender.blocking_send(parsed).is_err() {
+            // The writer gave up, nobody is listening anymore.
+            return Ok(None);
+        }
+    }
+}
+
+/// Write and drain `batch`. A failed batch is reported row by row rather
+/// than aborting the import.
+async fn write_batch(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &ApplicationBaseUrl,
+    options: &ImportOptions,
+    batch: &mut Vec<(u64, NewSubscriber)>,
+    report: &mut ImportReport,
+) {
+    if batch.is_empty() {
+        return;
+    }
+    let rows = std::mem::take(batch);
+    let inserted = match insert_batch(pool, options.status, &rows).await {
+        Ok(inserted) => inserted,
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e, "Failed to import a batch of subscribers");
+            report.errors.extend(rows.iter().map(|(line, _)| RowError {
+                line: *line,
+                code: "database_error",
+                message: "The batch containing this row could not be saved.".into(),
+            }));
+            return;
+        }
+    };
+
+    let mut confirmations = Vec::new();
+    for ((line, subscriber), outcome) in rows.into_iter().zip(inserted) {
+        let token = match outcome {
+            Inserted::Duplicate => {
+                report.duplicates.push(DuplicateRow {
+                    line,
+                    email: subscriber.email.as_ref().to_owned(),
+                });
+                continue;
+            }
+            Inserted::Confirmed => None,
+            Inserted::Pending { token } => Some(token),
+        };
+        report.imported += 1;
+        if let Some(token) = token.filter(|_| options.send_confirmation) {
+            confirmations.push((line, subscriber, token));
+        }
+    }
+
+    // In order, so that failures are reported in line order.
+    let mut sent = futures_util::stream::iter(confirmations)
+        .map(|(line, subscriber, token)| async move {
+            let outcome =
+                send_confirmation_email(email_client, subscriber, base_url.clone(), &token).await;
+            (line, outcome)
+        })
+        .buffered(CONCURRENT_EMAILS);
+    while let Some((line, outcome)) = sent.next().await {
+        if let Err(e) = outcome {
+            tracing::warn!(error.cause_chain = ?e, "Failed to send a confirmation email");
+            report.errors.push(RowError {
+                line,
+                code: "confirmation_email_failed",
+                message:
+                    "The subscriber was imported, but the confirmation email could not be sent."
+                        .into(),
+            });
+        }
+    }
+}
+
+enum Inserted {
+    /// The email was already subscribed, nothing was written.
+    Duplicate,
+    Confirmed,
+    Pending {
+        token: String,
+    },
+}
+
+async fn insert_batch(
+    pool: &PgPool,
+    status: ImportStatus,
+    rows: &[(u64, NewSubscriber)],
+) -> Result<Vec<Inserted>, ImportError> {
+    let mut transaction = pool.begin().await.map_err(ImportError::DatabaseError)?;
+    let mut inserted = Vec::with_capacity(rows.len());
+    for (_, subscriber) in rows {
+        let outcome = match insert_if_new(&mut transaction, subscriber, status).await? {
+            None => Inserted::Duplicate,
+            Some(_) if status == ImportStatus::Confirmed => Inserted::Confirmed,
+            Some(subscriber_id) => {
+                let token = generate_subscriptions_token();
+                store_token(&mut transaction, subscriber_id, &token)
+                    .await
+                    .map_err(ImportError::DatabaseError)?;
+                Inserted::Pending { token }
+            }
+        };
+        inserted.push(outcome);
+    }
+    transaction
+        .commit()
+        .await
+        .map_err(ImportError::DatabaseError)?;
+    Ok(inserted)
+}
+
+/// Returns the ID of the new subscriber, `None` if the email is taken.
+async fn insert_if_new(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber: &NewSubscriber,
+    status: ImportStatus,
+) -> Result<Option<Uuid>, ImportError> {
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (email) DO NOTHING
+        RETURNING id
+        "#,
+        Uuid::new_v4(),
+        subscriber.email.as_ref(),
+        subscriber.name.as_ref(),
+        Utc::now(),
+        status.as_str(),
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .map_err(ImportError::DatabaseError)?;
+    Ok(row.map(|r| r.id))
+}
+
+#[derive(thiserror::Error)]
+pub enum ImportError {
+    #[error("{0}")]
+    InvalidOptions(&'static str),
+    #[error("The CSV header must contain `email` and `name` columns.")]
+    MissingColumns,
+    #[error("Failed to read the CSV input.")]
+    ReadError(#[source] csv::Error),
+    #[error("A database error was encountered while importing subscribers.")]
+    DatabaseError(#[source] sqlx::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_format(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ImportOptions, ImportStatus, ParsedRow};
+
+    #[test]
+    fn confirmation_emails_require_pending_status() {
+        assert!(ImportOptions::new(ImportStatus::Confirmed, true).is_err());
+        assert!(ImportOptions::new(ImportStatus::PendingConfirmation, true).is_ok());
+        assert!(ImportOptions::new(ImportStatus::Confirmed, false).is_ok());
+    }
+
+    #[test]
+    fn rows_are_validated_like_signups() {
+        let row = |email: &str, name: &str| {
+            let csv = format!("email,name\n{},{}\n", email, name);
+            let mut reader = csv::Reader::from_reader(csv.as_bytes());
+            let headers = reader.headers().unwrap().clone();
+            let record = reader.records().next().unwrap().unwrap();
+            ParsedRow::parse(2, record.deserialize(Some(&headers))).row
+        };
+
+        assert!(row("ursula@example.com", "Ursula").is_ok());
+        assert_eq!(
+            "invalid_email",
+            row("not-an-email", "Ursula").err().unwrap().code
+        );
+        assert_eq!(
+            "invalid_name",
+            row("ursula@example.com", "").err().unwrap().code
+        );
+    }
+}
@@ -1,9 +1,34 @@
+use std::sync::OnceLock;
+
+use anyhow::Context;
+use axum::http::HeaderMap;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::TraceError;
+use opentelemetry::KeyValue;
+use opentelemetry_http::{HeaderExtractor, HeaderInjector};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Tracer, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
 use tracing::{subscriber::set_global_default, Subscriber};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
-use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Registry};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, reload, EnvFilter, Registry};
+
+use crate::configuration::{OtlpProtocol, RedactionSettings, TracingSettings};
 
 /// Compose multiple layers into a `tracing`'s subscriber.
 ///
+/// When a `tracer` is given, spans also become OpenTelemetry spans, which
+/// is what carries the W3C trace context from incoming requests to
+/// outgoing ones.
+///
 /// # Implementation Notes
 ///
 /// We are using `impl Subscriber` as return type to avoid having to
@@ -16,6 +41,7 @@ pub fn get_subscriber<Sink>(
     name: String,
     env_filter: String,
     sink: Sink,
+    tracer: Option<Tracer>,
 ) -> impl Subscriber + Send + Sync
 where
     // Check out https://doc.rust-lang.org/nomicon/hrtb.html
@@ -23,16 +49,267 @@ where
 {
     let env_filter =
         EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
+    let (env_filter, handle) = reload::Layer::new(env_filter);
+    let _ = LOG_FILTER.set(handle);
     let formatting_layer = BunyanFormattingLayer::new(name, sink);
+    let otel_layer = tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer));
     Registry::default()
         .with(env_filter)
+        .with(otel_layer)
         .with(JsonStorageLayer)
         .with(formatting_layer)
 }
 
+static LOG_FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// Replace the filter of the subscriber built by [`get_subscriber`], e.g.
+/// with `info,zero2prod=debug`.
+pub fn set_log_filter(directives: &str) -> Result<(), anyhow::Error> {
+    let filter = EnvFilter::try_new(directives).context("Invalid log filter.")?;
+    LOG_FILTER
+        .get()
+        .context("No subscriber has been built.")?
+        .reload(filter)
+        .context("Failed to swap the log filter.")?;
+    Ok(())
+}
+
+/// The directives of the filter currently in effect, if a subscriber has
+/// been built.
+pub fn current_log_filter() -> Option<String> {
+    LOG_FILTER.get()?.with_current(ToString::to_string).ok()
+}
+
 /// Register a subscriber as global default to process span data.
 ///
 /// It should only be called once!
 pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     set_global_default(subscriber).expect("Failed to set subscriber");
 }
+
+/// Build the provider of the tracer handed to [`get_subscriber`].
+///
+/// Spans are exported in batches, from a thread of their own, when an
+/// OTLP endpoint is configured. Call `shutdown` on the provider before
+/// exiting to flush what is left.
+pub fn tracer_provider(settings: &TracingSettings) -> Result<TracerProvider, TraceError> {
+    let mut builder = TracerProvider::builder().with_resource(Resource::new([KeyValue::new(
+        "service.name",
+        settings.service_name.clone(),
+    )]));
+    if let Some(endpoint) = &settings.otlp_endpoint {
+        let exporter = match settings.otlp_protocol {
+            OtlpProtocol::Grpc => SpanExporter::builder()
+                .with_tonic()
+                .with_endpoint(endpoint)
+                .build()?,
+            OtlpProtocol::Http => SpanExporter::builder()
+                .with_http()
+                .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+                .build()?,
+        };
+        builder = builder.with_batch_exporter(exporter, runtime::TokioCurrentThread);
+    }
+    Ok(builder.build())
+}
+
+/// The trace context sent by the caller in a `traceparent` header, if any.
+pub fn extract_trace_context(headers: &HeaderMap) -> opentelemetry::Context {
+    TraceContextPropagator::new().extract(&HeaderExtractor(headers))
+}
+
+/// Add the `traceparent` header identifying the current span, so that the
+/// receiver can attach its work to our trace.
+pub fn inject_trace_context(headers: &mut HeaderMap) {
+    let context = tracing::Span::current().context();
+    TraceContextPropagator::new().inject_context(&context, &mut HeaderInjector(headers));
+}
+
+/// How personal data, such as subscribers' emails and names, shows up in
+/// logs, spans and error messages.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RedactionMode {
+    /// As is; only meant for local development.
+    Full,
+    /// Only the first character, and the domain of email addresses.
+    #[default]
+    Masked,
+    /// A keyed hash, so that entries about the same person can still be
+    /// correlated without revealing who they are.
+    Hashed,
+}
+
+pub struct RedactionPolicy {
+    mode: RedactionMode,
+    salt: Secret<String>,
+}
+
+impl RedactionPolicy {
+    pub fn new(mode: RedactionMode, salt: Secret<String>) -> Self {
+        Self { mode, salt }
+    }
+
+    pub fn redact(&self, value: &str) -> String {
+        match self.mode {
+            RedactionMode::Full => value.to_owned(),
+            RedactionMode::Masked => mask(value),
+            RedactionMode::Hashed => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(self.salt.expose_secret().as_bytes())
+                    .expect("HMAC accepts keys of any length");
+                mac.update(value.as_bytes());
+                let digest = mac.finalize().into_bytes();
+                format!("hash:{}", URL_SAFE_NO_PAD.encode(&digest[..9]))
+            }
+        }
+    }
+}
+
+fn mask(value: &str) -> String {
+    let (head, domain) = match value.rsplit_once('@') {
+        Some((local, domain)) => (local, Some(domain)),
+        None => (value, None),
+    };
+    let mut masked: String = head.chars().take(1).collect();
+    masked.push_str("***");
+    if let Some(domain) = domain {
+        masked.push('@');
+        masked.push_str(domain);
+    }
+    masked
+}
+
+static REDACTION: OnceLock<RedactionPolicy> = OnceLock::new();
+
+/// Set the policy applied by [`Redacted`] for the rest of the process.
+///
+/// Only the first call has an effect; values are masked until then.
+pub fn init_redaction(settings: &RedactionSettings) {
+    let _ = REDACTION.set(RedactionPolicy::new(settings.mode, settings.salt.clone()));
+}
+
+/// Personal data, displayed according to the policy set with
+/// [`init_redaction`].
+///
+/// Wrap values in it wherever they might reach a log line, a span field or
+/// an error message, e.g. `fields(subscriber_email = %Redacted(&email))`.
+pub struct Redacted<T>(pub T);
+
+impl<T: AsRef<str>> std::fmt::Display for Redacted<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = self.0.as_ref();
+        match REDACTION.get() {
+            Some(policy) => f.write_str(&policy.redact(value)),
+            None => f.write_str(&mask(value)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::TracerProvider as _;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn settings(otlp_endpoint: Option<String>) -> TracingSettings {
+        TracingSettings {
+            otlp_endpoint,
+            otlp_protocol: OtlpProtocol::Http,
+            service_name: "zero2prod-test".into(),
+            log_filter: "info".into(),
+            redaction: RedactionSettings {
+                mode: RedactionMode::Masked,
+                salt: Secret::new("salt".into()),
+            },
+        }
+    }
+
+    fn policy(mode: RedactionMode, salt: &str) -> RedactionPolicy {
+        RedactionPolicy::new(mode, Secret::new(salt.into()))
+    }
+
+    #[test]
+    fn masking_keeps_the_first_character_and_the_email_domain() {
+        let masked = policy(RedactionMode::Masked, "");
+        assert_eq!(masked.redact("ursula_le_guin@gmail.com"), "u***@gmail.com");
+        assert_eq!(masked.redact("Ursula Le Guin"), "U***");
+        assert_eq!(masked.redact(""), "***");
+    }
+
+    #[test]
+    fn hashes_are_stable_and_depend_on_the_salt() {
+        let hashed = policy(RedactionMode::Hashed, "salt");
+        let email = "ursula_le_guin@gmail.com";
+
+        let hash = hashed.redact(email);
+        assert!(hash.starts_with("hash:"));
+        assert!(!hash.contains("ursula"));
+        assert_eq!(hash, hashed.redact(email));
+        assert_ne!(hash, policy(RedactionMode::Hashed, "pepper").redact(email));
+    }
+
+    #[test]
+    fn the_full_mode_leaves_values_untouched() {
+        let full = policy(RedactionMode::Full, "");
+        assert_eq!(
+            full.redact("ursula_le_guin@gmail.com"),
+            "ursula_le_guin@gmail.com"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn spans_are_exported_to_the_collector_over_http() {
+        let collector = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/traces"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1..)
+            .mount(&collector)
+            .await;
+        let provider = tracer_provider(&settings(Some(collector.uri()))).unwrap();
+        let subscriber = get_subscriber(
+            "test".into(),
+            "info".into(),
+            std::io::sink,
+            Some(provider.tracer("test")),
+        );
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("exported").in_scope(|| {});
+        });
+        tokio::task::spawn_blocking(move || provider.shutdown())
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[test]
+    fn the_current_trace_context_round_trips_through_headers() {
+        let provider = tracer_provider(&settings(None)).unwrap();
+        let subscriber = get_subscriber(
+            "test".into(),
+            "info".into(),
+            std::io::sink,
+            Some(provider.tracer("test")),
+        );
+        let mut incoming = HeaderMap::new();
+        incoming.insert(
+            "traceparent",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+                .parse()
+                .unwrap(),
+        );
+
+        let mut outgoing = HeaderMap::new();
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("request");
+            span.set_parent(extract_trace_context(&incoming));
+            span.in_scope(|| inject_trace_context(&mut outgoing));
+        });
+
+        let traceparent = outgoing["traceparent"].to_str().unwrap();
+        assert!(traceparent.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+        assert!(!traceparent.contains("00f067aa0ba902b7"));
+    }
+}
@@ -0,0 +1,152 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Context;
+use arc_swap::ArcSwap;
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+use tokio::sync::mpsc;
+use tokio_rustls::TlsAcceptor;
+
+use crate::configuration::TlsSettings;
+use crate::reload::{debounce, watch_directories};
+use crate::shutdown::Shutdown;
+
+/// The certificate served to every client, read again from its PEM files
+/// by [`reload_on_change`].
+pub struct ReloadingCertificate {
+    settings: TlsSettings,
+    provider: Arc<CryptoProvider>,
+    current: ArcSwap<CertifiedKey>,
+}
+
+impl ReloadingCertificate {
+    pub fn new(settings: TlsSettings) -> Result<Self, anyhow::Error> {
+        let provider = Arc::new(ring::default_provider());
+        let current = ArcSwap::from_pointee(load_certified_key(&settings, &provider)?);
+        Ok(Self {
+            settings,
+            provider,
+            current,
+        })
+    }
+
+    /// Swap in the certificate currently on disk, keeping the one being
+    /// served if it cannot be read.
+    pub fn reload(&self) {
+        match load_certified_key(&self.settings, &self.provider) {
+            Ok(key) => {
+                self.current.store(Arc::new(key));
+                tracing::info!("Reloaded the TLS certificate");
+            }
+            Err(e) => {
+                tracing::error!(error.cause_chain = ?e, "Kept the current TLS certificate, the new one is invalid")
+            }
+        }
+    }
+
+    /// An acceptor offering HTTP/2 and HTTP/1.1 through ALPN.
+    pub fn acceptor(self: &Arc<Self>) -> Result<TlsAcceptor, anyhow::Error> {
+        let mut config = ServerConfig::builder_with_provider(self.provider.clone())
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_cert_resolver(self.clone());
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+impl ResolvesServerCert for ReloadingCertificate {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.load_full())
+    }
+}
+
+impl std::fmt::Debug for ReloadingCertificate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReloadingCertificate")
+            .field("certificate_path", &self.settings.certificate_path)
+            .finish_non_exhaustive()
+    }
+}
+
+fn load_certified_key(
+    settings: &TlsSettings,
+    provider: &CryptoProvider,
+) -> Result<CertifiedKey, anyhow::Error> {
+    let certificates = rustls_pemfile::certs(&mut open(&settings.certificate_path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to parse the certificate chain.")?;
+    if certificates.is_empty() {
+        anyhow::bail!(
+            "There is no certificate in {}.",
+            settings.certificate_path.display()
+        );
+    }
+    let key = rustls_pemfile::private_key(&mut open(&settings.private_key_path)?)
+        .context("Failed to parse the private key.")?
+        .with_context(|| {
+            format!(
+                "There is no private key in {}.",
+                settings.private_key_path.display()
+            )
+        })?;
+    let key = provider.key_provider.load_private_key(key)?;
+    let certified_key = CertifiedKey::new(certificates, key);
+    // Files renewed one at a time are caught between the two writes.
+    certified_key.keys_match().with_context(|| {
+        format!(
+            "The private key in {} does not match the certificate in {}.",
+            settings.private_key_path.display(),
+            settings.certificate_path.display()
+        )
+    })?;
+    Ok(certified_key)
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, anyhow::Error> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}.", path.display()))?;
+    Ok(BufReader::new(file))
+}
+
+/// Reload the certificate whenever its files change, until shutdown.
+///
+/// The directories holding them are watched, rather than the files, so
+/// that certificates renewed by swapping a symlink are picked up too.
+pub async fn reload_on_change(certificate: Arc<ReloadingCertificate>, shutdown: Shutdown) {
+    let mut directories: Vec<&Path> = [
+        &certificate.settings.certificate_path,
+        &certificate.settings.private_key_path,
+    ]
+    .into_iter()
+    .filter_map(|path| path.parent())
+    .map(|directory| match directory.as_os_str().is_empty() {
+        true => Path::new("."),
+        false => directory,
+    })
+    .collect();
+    directories.dedup();
+    let (changes, mut changed) = mpsc::channel(1);
+    // Dropping the watcher stops it, keep it until we are done.
+    let _watcher = match watch_directories(&directories, changes) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e, "Failed to watch the TLS certificate");
+            return;
+        }
+    };
+
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => return,
+            Some(()) = changed.recv() => {
+                debounce(&mut changed).await;
+                certificate.reload();
+            }
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use reqwest::Method;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use zero2prod::configuration::get_configuration;
+use zero2prod::startup::Application;
+
+use crate::helpers::{spawn_app, spawn_app_with};
+
+#[tokio::test]
+async fn requests_missing_authorization_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/blocked_domains", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="admin""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn invalid_password_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/blocked_domains", &app.address))
+        .basic_auth(&app.test_user.username, Some("not-the-password"))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn blocked_domains_are_rejected_on_subscribe() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .admin_request(Method::PUT, "/admin/blocked_domains/spam.io")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(201, response.status().as_u16());
+
+    let denied: Vec<String> = app
+        .admin_request(Method::GET, "/admin/blocked_domains")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(denied, vec!["spam.io".to_string()]);
+
+    let response = app
+        .post_subscriptions_json(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula@spam.io"
+        }))
+        .await;
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["errors"][0]["code"], "blocked_domain");
+}
+
+#[tokio::test]
+async fn unblocked_domains_are_accepted_again() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.admin_request(Method::PUT, "/admin/blocked_domains/spam.io")
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let response = app
+        .admin_request(Method::DELETE, "/admin/blocked_domains/spam.io")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(204, response.status().as_u16());
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula%40spam.io".into())
+        .await;
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn blocked_domains_are_kept_across_restarts() {
+    let app = spawn_app().await;
+    app.admin_request(Method::PUT, "/admin/blocked_domains/spam.io")
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let mut configuration = get_configuration().unwrap();
+    configuration.database.database_name = app
+        .db_pool
+        .connect_options()
+        .get_database()
+        .unwrap()
+        .to_owned();
+    configuration.application.port = 0;
+    configuration.metrics.port = Some(0);
+    let restarted = Application::build(configuration).await.unwrap();
+    let address = format!("http://127.0.0.1:{}", restarted.port());
+    tokio::spawn(restarted.run_untill_stopped());
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("name=le%20guin&email=ursula%40spam.io")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["errors"][0]["code"], "blocked_domain");
+}
+
+#[tokio::test]
+async fn configured_domains_cannot_be_unblocked() {
+    let app = spawn_app_with(|c| c.domain_policy.deny_list = vec!["spam.io".into()]).await;
+
+    let response = app
+        .admin_request(Method::DELETE, "/admin/blocked_domains/spam.io")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(409, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["type"], "/problems/configured-domain");
+}
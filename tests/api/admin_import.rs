@@ -0,0 +1,199 @@
+use axum::body::Bytes;
+use reqwest::Method;
+use tokio_util::io::StreamReader;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::domain::DomainPolicy;
+use zero2prod::startup::ApplicationBaseUrl;
+use zero2prod::subscriber_import::{import_subscribers, ImportOptions, ImportStatus};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn upload(app: &TestApp, query: &[(&str, &str)], csv: &'static str) -> reqwest::Response {
+    app.admin_request(Method::POST, "/admin/subscribers/import")
+        .query(query)
+        .header("Content-Type", "text/csv")
+        .body(csv)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn importing_requires_authentication() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/subscribers/import", &app.address))
+        .body("email,name\nursula@example.com,Ursula\n")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn valid_rows_are_imported_as_confirmed_and_the_rest_reported() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    let csv = "name,email,source\n\
+        Octavia,octavia@example.com,old-provider\n\
+        ,nameless@example.com,old-provider\n\
+        Ursula,ursula_le_guin@gmail.com,old-provider\n\
+        Ted,not-an-email,old-provider\n\
+        Octavia again,octavia@example.com,old-provider\n\
+        Becky,becky@example.com,old-provider\n";
+    let response = upload(&app, &[], csv).await;
+
+    assert_eq!(200, response.status().as_u16());
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(2, report["imported"]);
+    assert_eq!(
+        serde_json::json!([
+            {"line": 4, "email": "ursula_le_guin@gmail.com"},
+            {"line": 6, "email": "octavia@example.com"}
+        ]),
+        report["duplicates"]
+    );
+    let errors: Vec<(u64, &str)> = report["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| (e["line"].as_u64().unwrap(), e["code"].as_str().unwrap()))
+        .collect();
+    assert_eq!(vec![(3, "invalid_name"), (5, "invalid_email")], errors);
+
+    let imported = sqlx::query!(
+        "SELECT email, status FROM subscriptions WHERE email LIKE '%@example.com' ORDER BY email"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    let imported: Vec<(&str, &str)> = imported
+        .iter()
+        .map(|r| (r.email.as_str(), r.status.as_str()))
+        .collect();
+    assert_eq!(
+        vec![
+            ("becky@example.com", "confirmed"),
+            ("octavia@example.com", "confirmed")
+        ],
+        imported
+    );
+}
+
+#[tokio::test]
+async fn rows_refused_by_the_domain_policy_are_reported() {
+    let app = spawn_app().await;
+
+    let csv = "email,name\n\
+        octavia@example.com,Octavia\n\
+        ursula@mailinator.com,Ursula\n";
+    let response = upload(&app, &[], csv).await;
+
+    assert_eq!(200, response.status().as_u16());
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(1, report["imported"]);
+    assert_eq!(3, report["errors"][0]["line"]);
+    assert_eq!("disposable_domain", report["errors"][0]["code"]);
+}
+
+#[tokio::test]
+async fn pending_imports_can_be_sent_a_confirmation_email() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let csv = "email,name\noctavia@example.com,Octavia\nbecky@example.com,Becky\n";
+    let response = upload(
+        &app,
+        &[
+            ("status", "pending_confirmation"),
+            ("send_confirmation", "true"),
+        ],
+        csv,
+    )
+    .await;
+
+    assert_eq!(200, response.status().as_u16());
+    let tokens = sqlx::query!("SELECT count(*) AS \"count!\" FROM subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(2, tokens.count);
+}
+
+#[tokio::test]
+async fn confirmed_imports_cannot_be_sent_a_confirmation_email() {
+    let app = spawn_app().await;
+
+    let response = upload(
+        &app,
+        &[("send_confirmation", "true")],
+        "email,name\noctavia@example.com,Octavia\n",
+    )
+    .await;
+
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn csv_without_the_required_columns_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = upload(
+        &app,
+        &[],
+        "address,full_name\noctavia@example.com,Octavia\n",
+    )
+    .await;
+
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("/problems/invalid-csv", body["type"]);
+}
+
+#[tokio::test]
+async fn rows_read_before_the_input_fails_are_imported() {
+    let app = spawn_app().await;
+    let chunks = vec![
+        Ok(Bytes::from_static(
+            b"email,name\noctavia@example.com,Octavia\nbecky@example.com,Becky\n",
+        )),
+        Err(std::io::Error::other("connection reset")),
+    ];
+    let input = StreamReader::new(futures_util::stream::iter(chunks));
+
+    let report = import_subscribers(
+        input,
+        &app.db_pool,
+        &DomainPolicy::new(vec![], vec![], true),
+        &app.email_client,
+        &ApplicationBaseUrl(app.address.clone()),
+        &ImportOptions::new(ImportStatus::Confirmed, false).unwrap(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(2, report.imported);
+    assert!(report.read_error.unwrap().contains("connection reset"));
+    let imported = sqlx::query!("SELECT count(*) AS \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(2, imported.count);
+}
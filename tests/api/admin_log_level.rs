@@ -0,0 +1,86 @@
+use std::time::Duration;
+
+use reqwest::Method;
+
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn changing_the_log_level_requires_authentication() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .put(format!("{}/admin/log-level", &app.address))
+        .json(&serde_json::json!({ "filter": "debug" }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn invalid_filters_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .admin_request(Method::PUT, "/admin/log-level")
+        .json(&serde_json::json!({ "filter": "zero2prod=loud" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["type"], "/problems/invalid-log-filter");
+}
+
+// The only test changing the filter, which is shared by the whole process.
+#[tokio::test]
+async fn the_log_level_reverts_to_the_default_after_its_ttl() {
+    let app = spawn_app().await;
+
+    let response = app
+        .admin_request(Method::PUT, "/admin/log-level")
+        .json(&serde_json::json!({ "filter": "info,zero2prod=debug", "ttl_seconds": 1 }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body["filter"].as_str().unwrap().contains("zero2prod=debug"));
+    assert_eq!(body["default"], "info");
+    assert!(body["revert_at"].is_string());
+
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let body: serde_json::Value = app
+        .admin_request(Method::GET, "/admin/log-level")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(body["filter"], "info");
+    assert!(body["revert_at"].is_null());
+}
+
+#[tokio::test]
+async fn a_ttl_longer_than_a_week_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .admin_request(Method::PUT, "/admin/log-level")
+        .json(&serde_json::json!({ "filter": "debug", "ttl_seconds": u64::MAX }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(400, response.status().as_u16());
+
+    let response = app
+        .admin_request(Method::GET, "/admin/log-level")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, response.status().as_u16());
+}
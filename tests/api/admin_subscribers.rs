@@ -0,0 +1,215 @@
+use chrono::{Duration, Utc};
+use reqwest::Method;
+use uuid::Uuid;
+
+use zero2prod::configuration::get_configuration;
+
+use crate::helpers::{configure_database, spawn_app, spawn_app_with, TestApp};
+
+async fn insert_subscriber(app: &TestApp, name: &str, email: &str, status: &str, age_minutes: i64) {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        Uuid::new_v4(),
+        email,
+        name,
+        Utc::now() - Duration::minutes(age_minutes),
+        status,
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("Failed to insert subscriber.");
+}
+
+async fn seed(app: &TestApp) {
+    insert_subscriber(app, "ursula", "ursula@example.com", "confirmed", 1).await;
+    insert_subscriber(
+        app,
+        "octavia",
+        "octavia@example.com",
+        "pending_confirmation",
+        2,
+    )
+    .await;
+    insert_subscriber(app, "ted", "ted@example.org", "confirmed", 3).await;
+    insert_subscriber(app, "becky", "becky@example.org", "confirmed", 4).await;
+}
+
+async fn list(app: &TestApp, query: &[(&str, &str)]) -> serde_json::Value {
+    let response = app
+        .admin_request(Method::GET, "/admin/subscribers")
+        .query(query)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+    response.json().await.unwrap()
+}
+
+fn emails(page: &serde_json::Value) -> Vec<&str> {
+    page["subscribers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["email"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn listing_subscribers_requires_authentication() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/subscribers", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn subscribers_are_listed_newest_first_across_pages() {
+    let app = spawn_app().await;
+    seed(&app).await;
+
+    let first = list(&app, &[("limit", "3")]).await;
+    assert_eq!(
+        vec![
+            "ursula@example.com",
+            "octavia@example.com",
+            "ted@example.org"
+        ],
+        emails(&first)
+    );
+    let cursor = first["next_cursor"].as_str().unwrap();
+
+    let second = list(&app, &[("limit", "3"), ("cursor", cursor)]).await;
+    assert_eq!(vec!["becky@example.org"], emails(&second));
+    assert!(second["next_cursor"].is_null());
+}
+
+#[tokio::test]
+async fn subscribers_can_be_filtered_searched_and_sorted() {
+    let app = spawn_app().await;
+    seed(&app).await;
+
+    let pending = list(&app, &[("status", "pending_confirmation")]).await;
+    assert_eq!(vec!["octavia@example.com"], emails(&pending));
+
+    let search = list(&app, &[("q", "EXAMPLE.ORG"), ("status", "confirmed")]).await;
+    assert_eq!(
+        vec!["ted@example.org", "becky@example.org"],
+        emails(&search)
+    );
+
+    let by_email = list(&app, &[("sort", "email"), ("order", "asc"), ("limit", "2")]).await;
+    assert_eq!(
+        vec!["becky@example.org", "octavia@example.com"],
+        emails(&by_email)
+    );
+    let cursor = by_email["next_cursor"].as_str().unwrap();
+    let rest = list(
+        &app,
+        &[
+            ("sort", "email"),
+            ("order", "asc"),
+            ("limit", "2"),
+            ("cursor", cursor),
+        ],
+    )
+    .await;
+    assert_eq!(vec!["ted@example.org", "ursula@example.com"], emails(&rest));
+}
+
+#[tokio::test]
+async fn a_cursor_cannot_be_reused_with_another_ordering() {
+    let app = spawn_app().await;
+    seed(&app).await;
+
+    let page = list(&app, &[("limit", "1")]).await;
+    let cursor = page["next_cursor"].as_str().unwrap();
+
+    let response = app
+        .admin_request(Method::GET, "/admin/subscribers")
+        .query(&[("sort", "name"), ("cursor", cursor)])
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("/problems/invalid-cursor", body["type"]);
+}
+
+#[tokio::test]
+async fn subscribers_can_be_downloaded_as_csv() {
+    let app = spawn_app().await;
+    seed(&app).await;
+
+    let response = app
+        .admin_request(Method::GET, "/admin/subscribers")
+        .query(&[("status", "confirmed"), ("sort", "name"), ("order", "asc")])
+        .header("Accept", "text/csv")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        "text/csv; charset=utf-8",
+        response.headers()["Content-Type"]
+    );
+    let body = response.text().await.unwrap();
+    let lines: Vec<&str> = body.lines().collect();
+    assert_eq!("id,email,name,status,subscribed_at", lines[0]);
+    assert_eq!(4, lines.len());
+    assert!(lines[1].contains("becky@example.org"));
+    assert!(lines[3].contains("ursula@example.com"));
+}
+
+#[tokio::test]
+async fn subscribers_are_listed_from_the_read_replica() {
+    let mut replica = get_configuration().unwrap().database;
+    replica.database_name = Uuid::new_v4().to_string();
+    let replica_pool = configure_database(&replica).await;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, 'replica@example.com', 'replica', now(), 'confirmed')
+        "#,
+        Uuid::new_v4(),
+    )
+    .execute(&replica_pool)
+    .await
+    .unwrap();
+    let app = spawn_app_with(|c| c.read_replica = Some(replica)).await;
+    seed(&app).await;
+
+    let page = list(&app, &[]).await;
+
+    assert_eq!(emails(&page), ["replica@example.com"]);
+}
+
+#[tokio::test]
+async fn subscribers_are_listed_from_the_primary_while_the_replica_is_down() {
+    let app = spawn_app_with(|c| {
+        let mut replica = c.database.clone();
+        replica.port = 1;
+        replica.acquire_timeout_milliseconds = 60_000;
+        c.read_replica = Some(replica);
+    })
+    .await;
+    seed(&app).await;
+
+    // Well within the replica's acquire timeout.
+    let started = std::time::Instant::now();
+    let first = list(&app, &[]).await;
+    let second = list(&app, &[]).await;
+
+    assert_eq!(emails(&first).len(), 4);
+    assert_eq!(emails(&second).len(), 4);
+    assert!(started.elapsed() < std::time::Duration::from_secs(30));
+}
@@ -0,0 +1,148 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::bot_defence::verify_proof_of_work;
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+#[derive(serde::Deserialize)]
+struct FormToken {
+    form_token: String,
+    proof_of_work_bits: u8,
+}
+
+impl TestApp {
+    async fn get_form_token(&self) -> FormToken {
+        reqwest::get(format!("{}/subscriptions/form_token", &self.address))
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap()
+    }
+
+    async fn subscriber_count(&self) -> i64 {
+        sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+            .fetch_one(&self.db_pool)
+            .await
+            .unwrap()
+            .count
+    }
+}
+
+async fn expect_emails(app: &TestApp, count: u64) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(count)
+        .mount(&app.email_server)
+        .await;
+}
+
+#[tokio::test]
+async fn a_filled_honeypot_gets_a_fake_success() {
+    let app = spawn_app().await;
+    expect_emails(&app, 0).await;
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com&website=http%3A%2F%2Fspam.example";
+    let response = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(0, app.subscriber_count().await);
+}
+
+#[tokio::test]
+async fn a_missing_form_token_gets_a_fake_success_when_required() {
+    let app = spawn_app_with(|c| c.bot_defence.require_form_token = true).await;
+    expect_emails(&app, 0).await;
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let response = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(0, app.subscriber_count().await);
+}
+
+#[tokio::test]
+async fn forms_submitted_too_quickly_get_a_fake_success() {
+    let app = spawn_app_with(|c| c.bot_defence.min_fill_seconds = 60).await;
+    expect_emails(&app, 0).await;
+    let token = app.get_form_token().await;
+
+    let body = format!(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&form_token={}",
+        token.form_token
+    );
+    let response = app.post_subscriptions(body).await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(0, app.subscriber_count().await);
+}
+
+#[tokio::test]
+async fn a_form_solving_the_proof_of_work_is_accepted() {
+    let app = spawn_app_with(|c| {
+        c.bot_defence.min_fill_seconds = 0;
+        c.bot_defence.proof_of_work_bits = 8;
+    })
+    .await;
+    expect_emails(&app, 1).await;
+    let token = app.get_form_token().await;
+    assert_eq!(8, token.proof_of_work_bits);
+    let nonce = (0u64..)
+        .map(|n| n.to_string())
+        .find(|n| verify_proof_of_work(&token.form_token, "ursula_le_guin@gmail.com", n, 8))
+        .unwrap();
+
+    let body = format!(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&form_token={}&proof_of_work={}",
+        token.form_token, nonce
+    );
+    let response = app.post_subscriptions(body).await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(1, app.subscriber_count().await);
+}
+
+#[tokio::test]
+async fn a_replayed_form_token_gets_a_fake_success() {
+    let app = spawn_app_with(|c| c.bot_defence.min_fill_seconds = 0).await;
+    expect_emails(&app, 1).await;
+    let token = app.get_form_token().await;
+
+    for email in ["ursula_le_guin%40gmail.com", "octavia_butler%40gmail.com"] {
+        let body = format!(
+            "name=le%20guin&email={}&form_token={}",
+            email, token.form_token
+        );
+        let response = app.post_subscriptions(body).await;
+        assert_eq!(200, response.status().as_u16());
+    }
+
+    assert_eq!(1, app.subscriber_count().await);
+}
+
+#[tokio::test]
+async fn a_rejected_form_can_be_corrected_and_sent_again() {
+    let app = spawn_app_with(|c| c.bot_defence.min_fill_seconds = 0).await;
+    expect_emails(&app, 1).await;
+    let token = app.get_form_token().await;
+
+    let typo = format!(
+        "name=le%20guin&email=ursula_le_guingmail.com&form_token={}",
+        token.form_token
+    );
+    let response = app.post_subscriptions(typo).await;
+    assert_eq!(400, response.status().as_u16());
+
+    let corrected = format!(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&form_token={}",
+        token.form_token
+    );
+    let response = app.post_subscriptions(corrected).await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(1, app.subscriber_count().await);
+}
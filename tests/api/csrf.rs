@@ -0,0 +1,150 @@
+use reqwest::Method;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::configuration::CsrfMode;
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+const CSV: &str = "name,email\nOctavia,octavia@example.com\n";
+
+async fn subscribe_from(app: &TestApp, header: &str, value: &str) -> reqwest::Response {
+    let body = format!("name=le%20guin&email={}%40gmail.com", uuid::Uuid::new_v4());
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header(header, value)
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn mount_email_server(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+/// Issue a token as the test user, with the cookie set along with it.
+async fn csrf_token(app: &TestApp) -> (String, Option<String>) {
+    let response = app
+        .admin_request(Method::GET, "/admin/csrf-token")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+    let set_cookie = response
+        .headers()
+        .get("Set-Cookie")
+        .map(|value| value.to_str().unwrap().to_owned());
+    let body: serde_json::Value = response.json().await.unwrap();
+    (body["csrf_token"].as_str().unwrap().to_owned(), set_cookie)
+}
+
+/// Post a CSV import as plain text, as a form on another site could.
+fn plain_text_import(app: &TestApp) -> reqwest::RequestBuilder {
+    app.admin_request(Method::POST, "/admin/subscribers/import")
+        .header("Content-Type", "text/plain")
+        .body(CSV)
+}
+
+#[tokio::test]
+async fn subscribing_from_an_untrusted_origin_is_refused() {
+    let app = spawn_app().await;
+
+    let response = subscribe_from(&app, "Origin", "https://evil.example.com").await;
+
+    assert_eq!(403, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["type"], "/problems/csrf-rejected");
+}
+
+#[tokio::test]
+async fn the_referer_is_checked_when_there_is_no_origin() {
+    let app = spawn_app().await;
+
+    let response = subscribe_from(&app, "Referer", "https://evil.example.com/page").await;
+
+    assert_eq!(403, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn the_signup_widget_can_subscribe_from_trusted_origins() {
+    let app = spawn_app_with(|c| {
+        c.csrf.trusted_origins = vec!["https://www.example.com".into()];
+        c.application.http.cors.allowed_origins = vec!["https://blog.example.com".into()];
+    })
+    .await;
+    mount_email_server(&app).await;
+
+    for origin in ["https://www.example.com", "https://blog.example.com"] {
+        let response = subscribe_from(&app, "Origin", origin).await;
+        assert_eq!(200, response.status().as_u16());
+    }
+}
+
+#[tokio::test]
+async fn admin_form_posts_require_a_token() {
+    let app = spawn_app().await;
+
+    let response = plain_text_import(&app).send().await.unwrap();
+
+    assert_eq!(403, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn admin_form_posts_with_a_token_are_accepted() {
+    let app = spawn_app().await;
+    let (token, set_cookie) = csrf_token(&app).await;
+    assert!(set_cookie.is_none());
+
+    let response = plain_text_import(&app)
+        .header("X-CSRF-Token", token)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn admin_requests_a_browser_must_preflight_need_no_token() {
+    let app = spawn_app().await;
+
+    let response = app
+        .admin_request(Method::POST, "/admin/subscribers/import")
+        .header("Content-Type", "text/csv")
+        .body(CSV)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn double_submit_requires_the_token_cookie() {
+    let app = spawn_app_with(|c| c.csrf.admin_mode = CsrfMode::DoubleSubmit).await;
+    let (token, set_cookie) = csrf_token(&app).await;
+    let set_cookie = set_cookie.expect("No cookie was set");
+    assert!(set_cookie.starts_with(&format!("csrf_token={token};")));
+
+    let without_cookie = plain_text_import(&app)
+        .header("X-CSRF-Token", &token)
+        .send()
+        .await
+        .unwrap();
+    let with_cookie = plain_text_import(&app)
+        .header("X-CSRF-Token", &token)
+        .header("Cookie", format!("csrf_token={token}"))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(403, without_cookie.status().as_u16());
+    assert_eq!(200, with_cookie.status().as_u16());
+}
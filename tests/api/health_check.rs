@@ -1,4 +1,9 @@
-use crate::helpers::spawn_app;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, spawn_app_with};
 
 #[tokio::test]
 async fn health_check_works() {
@@ -14,3 +19,88 @@ async fn health_check_works() {
     assert!(response.status().is_success());
     assert_eq!(Some(0), response.content_length())
 }
+
+#[tokio::test]
+async fn unknown_routes_return_a_problem_details_404() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/does-not-exist", &app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(404, response.status().as_u16());
+    assert_eq!(
+        "application/problem+json",
+        response.headers()["Content-Type"]
+    );
+}
+
+#[tokio::test]
+async fn liveness_does_not_depend_on_anything() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/health/live", &app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(serde_json::json!({"status": "up"}), body);
+}
+
+#[tokio::test]
+async fn readiness_reports_each_dependency() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/health/ready", &app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("up", body["status"]);
+    assert_eq!("up", body["checks"]["database"]["status"]);
+    assert_eq!("up", body["checks"]["migrations"]["status"]);
+    assert!(body["checks"]["database"]["latency_ms"].is_u64());
+    assert!(body["checks"].get("email_provider").is_none());
+}
+
+#[tokio::test]
+async fn readiness_fails_when_the_schema_is_behind() {
+    let app = spawn_app().await;
+    sqlx::query(
+        "DELETE FROM _sqlx_migrations WHERE version = (SELECT max(version) FROM _sqlx_migrations)",
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let response = reqwest::get(format!("{}/health/ready", &app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(503, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("down", body["status"]);
+    assert_eq!("up", body["checks"]["database"]["status"]);
+    assert_eq!("down", body["checks"]["migrations"]["status"]);
+}
+
+#[tokio::test]
+async fn readiness_fails_when_the_email_provider_rejects_our_credentials() {
+    let app = spawn_app_with(|c| c.health.check_email_provider = true).await;
+    Mock::given(path("/server"))
+        .and(method("GET"))
+        .respond_with(ResponseTemplate::new(401))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = reqwest::get(format!("{}/health/ready", &app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(503, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("down", body["checks"]["email_provider"]["status"]);
+}
@@ -1,23 +1,50 @@
 use once_cell::sync::Lazy;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_sdk::trace::TracerProvider;
+use secrecy::{ExposeSecret, Secret};
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
 use wiremock::MockServer;
 use zero2prod::{
-    configuration::{get_configuration, DatabaseSettings},
+    authentication::compute_password_hash,
+    configuration::{get_configuration, DatabaseSettings, Settings},
+    email_client::EmailClient,
+    reload::Reloader,
+    shutdown::Shutdown,
     startup::{get_connection_pool, Application},
-    telemetry::{get_subscriber, init_subscriber},
+    telemetry::{get_subscriber, init_redaction, init_subscriber, tracer_provider},
 };
 
+static TRACER_PROVIDER: Lazy<TracerProvider> = Lazy::new(|| {
+    let settings = get_configuration().expect("Failed to read configuration.");
+    tracer_provider(&settings.tracing).expect("Failed to build the tracer provider.")
+});
+
 // Ensure that the `tracing` stack is only initialised once using `once_cell`
 static TRACING: Lazy<()> = Lazy::new(|| {
+    let settings = get_configuration().expect("Failed to read configuration.");
+    init_redaction(&settings.tracing.redaction);
     let default_filter_level = "info".into();
     let subscriber_name = "test".into();
 
+    // Nothing is exported, but trace context is propagated like in production.
+    let tracer = TRACER_PROVIDER.tracer("test");
+
     if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::stdout);
+        let subscriber = get_subscriber(
+            subscriber_name,
+            default_filter_level,
+            std::io::stdout,
+            Some(tracer),
+        );
         init_subscriber(subscriber);
     } else {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::sink);
+        let subscriber = get_subscriber(
+            subscriber_name,
+            default_filter_level,
+            std::io::sink,
+            Some(tracer),
+        );
         init_subscriber(subscriber);
     };
 });
@@ -27,6 +54,40 @@ pub struct TestApp {
     pub db_pool: PgPool,
     pub email_server: MockServer,
     pub port: u16,
+    pub test_user: TestUser,
+    /// Sends to `email_server`, for jobs that run outside the application.
+    pub email_client: EmailClient,
+    pub shutdown: Shutdown,
+    pub reloader: Reloader,
+    pub metrics_port: Option<u16>,
+    pub redirect_port: Option<u16>,
+}
+
+pub struct TestUser {
+    pub username: String,
+    pub password: String,
+}
+
+impl TestUser {
+    pub fn generate() -> Self {
+        Self {
+            username: Uuid::new_v4().to_string(),
+            password: Uuid::new_v4().to_string(),
+        }
+    }
+
+    async fn store(&self, pool: &PgPool) {
+        let password_hash = compute_password_hash(Secret::new(self.password.clone())).unwrap();
+        sqlx::query!(
+            "INSERT INTO users (user_id, username, password_hash) VALUES ($1, $2, $3)",
+            Uuid::new_v4(),
+            self.username,
+            password_hash.expose_secret(),
+        )
+        .execute(pool)
+        .await
+        .expect("Failed to store test user.");
+    }
 }
 
 pub struct ConfirmationLinks {
@@ -37,7 +98,7 @@ pub struct ConfirmationLinks {
 impl TestApp {
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -45,6 +106,15 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn post_subscriptions_json(&self, body: &serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
         // Extract the link from one of the request fields.
@@ -61,13 +131,20 @@ impl TestApp {
             confirmation_link.set_port(Some(self.port)).unwrap();
             confirmation_link
         };
-        let html = get_link(&body["HtmlBody"].as_str().unwrap());
-        let plain_text = get_link(&body["TextBody"].as_str().unwrap());
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
         ConfirmationLinks { html, plain_text }
     }
+
+    /// Send a request to an admin endpoint, authenticated as the test user.
+    pub fn admin_request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        reqwest::Client::new()
+            .request(method, format!("{}{}", &self.address, path))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+    }
 }
 
-async fn configure_database(config: &DatabaseSettings) -> PgPool {
+pub async fn configure_database(config: &DatabaseSettings) -> PgPool {
     let mut connection = PgConnection::connect_with(&config.without_db())
         .await
         .expect("Failed to connect to Postgres.");
@@ -91,6 +168,11 @@ async fn configure_database(config: &DatabaseSettings) -> PgPool {
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Spawn the application after letting the test tweak its configuration.
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Settings)) -> TestApp {
     // The first time `initialize` is invoked the code in `TRACING` is executed.
     // All other invocations will instead skip execution.
     Lazy::force(&TRACING);
@@ -99,7 +181,9 @@ pub async fn spawn_app() -> TestApp {
     let mut configuration = get_configuration().expect("Failed to read config.");
     configuration.database.database_name = Uuid::new_v4().to_string();
     configuration.application.port = 0;
-    configuration.email_client.base_url = email_server.uri();
+    configuration.metrics.port = Some(0);
+    configuration.email_client.base_url = email_server.uri().parse().unwrap();
+    configure(&mut configuration);
 
     configure_database(&configuration.database).await;
 
@@ -107,13 +191,25 @@ pub async fn spawn_app() -> TestApp {
         .await
         .expect("Failed to build application.");
     let application_port = application.port();
+    let shutdown = application.shutdown();
+    let reloader = application.reloader();
+    let metrics_port = application.metrics_port();
+    let redirect_port = application.redirect_port();
     let address = format!("http://127.0.0.1:{}", application.port());
-    let _ = tokio::spawn(application.run_untill_stopped());
+    tokio::spawn(application.run_untill_stopped());
 
-    TestApp {
+    let test_app = TestApp {
         address,
         port: application_port,
         db_pool: get_connection_pool(&configuration.database),
         email_server,
-    }
+        test_user: TestUser::generate(),
+        email_client: configuration.email_client.client(),
+        shutdown,
+        reloader,
+        metrics_port,
+        redirect_port,
+    };
+    test_app.test_user.store(&test_app.db_pool).await;
+    test_app
 }
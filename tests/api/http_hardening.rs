@@ -0,0 +1,142 @@
+use reqwest::Method;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+const WIDGET_ORIGIN: &str = "https://blog.example.com";
+
+async fn preflight(app: &TestApp, origin: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .request(Method::OPTIONS, format!("{}/subscriptions", &app.address))
+        .header("Origin", origin)
+        .header("Access-Control-Request-Method", "POST")
+        .header("Access-Control-Request-Headers", "content-type")
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn oversized_bodies_are_refused() {
+    let app = spawn_app_with(|c| c.application.http.max_body_bytes = 64).await;
+
+    let body = format!("name=le%20guin&email={}%40gmail.com", "a".repeat(64));
+    let response = app.post_subscriptions(body).await;
+
+    assert_eq!(413, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn subscriber_imports_are_exempt_from_the_body_limit() {
+    let app = spawn_app_with(|c| c.application.http.max_body_bytes = 64).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let csv = (0..10).fold("name,email\n".to_string(), |csv, i| {
+        csv + &format!("Reader {i},reader{i}@example.com\n")
+    });
+    let response = app
+        .admin_request(Method::POST, "/admin/subscribers/import")
+        .header("Content-Type", "text/csv")
+        .body(csv)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(10, report["imported"]);
+}
+
+#[tokio::test]
+async fn subscriber_imports_have_their_own_body_limit() {
+    let app = spawn_app_with(|c| c.application.http.max_import_bytes = 64).await;
+
+    let csv = (0..10).fold("name,email\n".to_string(), |csv, i| {
+        csv + &format!("Reader {i},reader{i}@example.com\n")
+    });
+    let response = app
+        .admin_request(Method::POST, "/admin/subscribers/import")
+        .header("Content-Type", "text/csv")
+        .body(csv)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(413, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn responses_are_compressed_for_clients_accepting_it() {
+    let app = spawn_app().await;
+
+    for encoding in ["gzip", "br"] {
+        let response = reqwest::Client::new()
+            .get(format!("{}/health/ready", &app.address))
+            .header("Accept-Encoding", encoding)
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        assert_eq!(response.headers()["Content-Encoding"], encoding);
+    }
+}
+
+#[tokio::test]
+async fn compression_can_be_turned_off() {
+    let app = spawn_app_with(|c| {
+        c.application.http.compression.gzip = false;
+        c.application.http.compression.br = false;
+    })
+    .await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/health/ready", &app.address))
+        .header("Accept-Encoding", "gzip, br")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert!(response.headers().get("Content-Encoding").is_none());
+}
+
+#[tokio::test]
+async fn the_signup_widget_origin_is_allowed_to_subscribe() {
+    let app = spawn_app_with(|c| {
+        c.application.http.cors.allowed_origins = vec![WIDGET_ORIGIN.into()];
+    })
+    .await;
+
+    let response = preflight(&app, WIDGET_ORIGIN).await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Access-Control-Allow-Origin"],
+        WIDGET_ORIGIN
+    );
+    let methods = response.headers()["Access-Control-Allow-Methods"]
+        .to_str()
+        .unwrap();
+    assert!(methods.contains("POST"));
+}
+
+#[tokio::test]
+async fn other_origins_are_not_allowed() {
+    let app = spawn_app_with(|c| {
+        c.application.http.cors.allowed_origins = vec![WIDGET_ORIGIN.into()];
+    })
+    .await;
+
+    let response = preflight(&app, "https://evil.example.com").await;
+
+    assert!(response
+        .headers()
+        .get("Access-Control-Allow-Origin")
+        .is_none());
+}
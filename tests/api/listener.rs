@@ -0,0 +1,108 @@
+use std::os::unix::fs::PermissionsExt;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use uuid::Uuid;
+use zero2prod::configuration::{get_configuration, ListenerSettings};
+use zero2prod::startup::Application;
+
+use crate::helpers::spawn_app_with;
+
+#[tokio::test]
+async fn requests_are_served_on_a_unix_socket() {
+    let path = std::env::temp_dir().join(format!("zero2prod-{}.sock", Uuid::new_v4()));
+    let listener = ListenerSettings::Unix {
+        path: path.clone(),
+        mode: Some("660".into()),
+    };
+    let app = spawn_app_with(|c| c.application.listener = listener).await;
+
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o660);
+
+    let mut stream = UnixStream::connect(&path).await.unwrap();
+    stream
+        .write_all(b"GET /health_check HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+
+    app.shutdown.trigger();
+    for _ in 0..50 {
+        if !path.exists() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("The socket was not removed on shutdown");
+}
+
+async fn post_subscription_over(path: &std::path::Path, forwarded_for: &str) -> String {
+    let body = "name=le%20guin";
+    let request = format!(
+        "POST /subscriptions HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+        X-Forwarded-For: {forwarded_for}\r\n\
+        Content-Type: application/x-www-form-urlencoded\r\n\
+        Content-Length: {}\r\n\r\n{body}",
+        body.len()
+    );
+    let mut stream = UnixStream::connect(path).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn forwarded_for_from_unix_socket_peers_is_ignored_by_default() {
+    let path = std::env::temp_dir().join(format!("zero2prod-{}.sock", Uuid::new_v4()));
+    let listener = ListenerSettings::Unix {
+        path: path.clone(),
+        mode: None,
+    };
+    let _app = spawn_app_with(|c| {
+        c.application.listener = listener;
+        c.rate_limit.per_ip.max_requests = 1;
+    })
+    .await;
+
+    let first = post_subscription_over(&path, "198.51.100.1").await;
+    let second = post_subscription_over(&path, "198.51.100.2").await;
+
+    assert!(first.starts_with("HTTP/1.1 422"), "{first}");
+    assert!(second.starts_with("HTTP/1.1 429"), "{second}");
+}
+
+#[tokio::test]
+async fn forwarded_for_from_unix_socket_peers_can_be_trusted() {
+    let path = std::env::temp_dir().join(format!("zero2prod-{}.sock", Uuid::new_v4()));
+    let listener = ListenerSettings::Unix {
+        path: path.clone(),
+        mode: None,
+    };
+    let _app = spawn_app_with(|c| {
+        c.application.listener = listener;
+        c.rate_limit.per_ip.max_requests = 1;
+        c.rate_limit.trust_unix_socket_peers = true;
+    })
+    .await;
+
+    let first = post_subscription_over(&path, "198.51.100.1").await;
+    let second = post_subscription_over(&path, "198.51.100.2").await;
+
+    assert!(first.starts_with("HTTP/1.1 422"), "{first}");
+    assert!(second.starts_with("HTTP/1.1 422"), "{second}");
+}
+
+#[tokio::test]
+async fn socket_activation_fails_without_a_socket_from_systemd() {
+    let mut configuration = get_configuration().unwrap();
+    configuration.application.listener = ListenerSettings::Systemd;
+
+    let result = Application::build(configuration).await;
+
+    assert!(result.is_err());
+}
@@ -1,5 +1,31 @@
+mod admin_blocked_domains;
+mod admin_import;
+mod admin_log_level;
+mod admin_subscribers;
+mod bot_defence;
+mod csrf;
+#[allow(clippy::needless_borrows_for_generic_args)]
 mod health_check;
 mod helpers;
+mod http_hardening;
+#[cfg(unix)]
+mod listener;
+mod maintenance;
+mod metrics;
+mod migration;
+#[allow(
+    unused_imports,
+    clippy::needless_borrow,
+    clippy::needless_borrows_for_generic_args
+)]
 mod newsletter;
+mod rate_limit;
+mod reload;
+mod request_id;
+mod shutdown;
+#[allow(clippy::needless_borrow)]
 mod subscriptions;
+#[allow(clippy::needless_borrow)]
 mod subscriptions_confirm;
+mod tls;
+mod trace_context;
@@ -0,0 +1,165 @@
+use chrono::{Duration, Utc};
+use secrecy::Secret;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::{
+    authentication::{change_password, create_user},
+    maintenance::{purge_stale_pending, resend_pending_confirmations},
+    startup::ApplicationBaseUrl,
+};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn insert_subscriber(app: &TestApp, email: &str, status: &str, age: Duration) -> Uuid {
+    let id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, $2, 'le guin', $3, $4)
+        "#,
+        id,
+        email,
+        Utc::now() - age,
+        status,
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("Failed to insert subscriber.");
+    id
+}
+
+async fn subscriber_emails(app: &TestApp) -> Vec<String> {
+    sqlx::query!("SELECT email FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.email)
+        .collect()
+}
+
+#[tokio::test]
+async fn purge_deletes_only_stale_pending_subscribers() {
+    let app = spawn_app().await;
+    let stale = insert_subscriber(
+        &app,
+        "stale@example.com",
+        "pending_confirmation",
+        Duration::days(40),
+    )
+    .await;
+    sqlx::query!(
+        "INSERT INTO subscription_tokens (subscription_token, subscriber_id) VALUES ('token', $1)",
+        stale
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    insert_subscriber(
+        &app,
+        "fresh@example.com",
+        "pending_confirmation",
+        Duration::days(1),
+    )
+    .await;
+    insert_subscriber(&app, "loyal@example.com", "confirmed", Duration::days(400)).await;
+
+    let deleted = purge_stale_pending(&app.db_pool, Duration::days(30))
+        .await
+        .unwrap();
+
+    assert_eq!(1, deleted);
+    assert_eq!(
+        vec!["fresh@example.com", "loyal@example.com"],
+        subscriber_emails(&app).await
+    );
+}
+
+#[tokio::test]
+async fn confirmations_are_resent_to_stale_pending_subscribers() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    insert_subscriber(
+        &app,
+        "stale@example.com",
+        "pending_confirmation",
+        Duration::days(2),
+    )
+    .await;
+    insert_subscriber(
+        &app,
+        "fresh@example.com",
+        "pending_confirmation",
+        Duration::minutes(5),
+    )
+    .await;
+    insert_subscriber(&app, "loyal@example.com", "confirmed", Duration::days(2)).await;
+
+    let report = resend_pending_confirmations(
+        &app.db_pool,
+        &app.email_client,
+        &ApplicationBaseUrl("http://127.0.0.1".into()),
+        Duration::hours(24),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(1, report.sent);
+    assert_eq!(0, report.failed);
+
+    // The resent link works.
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let link = app.get_confirmation_links(email_request).html;
+    let response = reqwest::get(link).await.unwrap();
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn admins_can_be_created_and_their_password_reset() {
+    let app = spawn_app().await;
+    let status = |username: &'static str, password: &'static str| {
+        let address = app.address.clone();
+        async move {
+            reqwest::Client::new()
+                .get(format!("{}/admin/blocked_domains", address))
+                .basic_auth(username, Some(password))
+                .send()
+                .await
+                .unwrap()
+                .status()
+                .as_u16()
+        }
+    };
+
+    create_user("ops", Secret::new("first".into()), &app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(200, status("ops", "first").await);
+    assert!(
+        create_user("ops", Secret::new("again".into()), &app.db_pool)
+            .await
+            .is_err()
+    );
+
+    assert!(
+        change_password("ops", Secret::new("second".into()), &app.db_pool)
+            .await
+            .unwrap()
+    );
+    assert_eq!(401, status("ops", "first").await);
+    assert_eq!(200, status("ops", "second").await);
+
+    assert!(
+        !change_password("nobody", Secret::new("x".into()), &app.db_pool)
+            .await
+            .unwrap()
+    );
+}
@@ -0,0 +1,117 @@
+use reqwest::Method;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+async fn scrape(app: &TestApp) -> String {
+    let metrics_port = app.metrics_port.expect("No separate metrics port");
+    let response = reqwest::get(format!("http://127.0.0.1:{}/metrics", metrics_port))
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain"));
+    response.text().await.unwrap()
+}
+
+#[tokio::test]
+async fn requests_are_counted_by_route_and_status() {
+    let app = spawn_app().await;
+    reqwest::get(format!("{}/health_check", &app.address))
+        .await
+        .unwrap();
+    reqwest::get(format!("{}/admin/blocked_domains/spam.io", &app.address))
+        .await
+        .unwrap();
+
+    let metrics = scrape(&app).await;
+
+    assert!(
+        metrics.contains(r#"http_requests_total{method="GET",path="/health_check",status="200"}"#)
+    );
+    assert!(metrics.contains(
+        r#"http_requests_total{method="GET",path="/admin/blocked_domains/:domain",status="405"}"#
+    ));
+    assert!(metrics.contains(r#"http_request_duration_seconds_bucket{method="GET",path="/health_check",status="200",le="0.005"}"#));
+    assert!(metrics.contains(r#"db_pool_connections{state="idle"}"#));
+    assert!(metrics.contains("db_pool_max_connections"));
+}
+
+#[tokio::test]
+async fn emails_and_subscribers_are_counted() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {"text": "Plain text", "html": "<p>HTML</p>"}
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    let metrics = scrape(&app).await;
+
+    assert!(metrics.contains("email_delivery_queue_depth"));
+    assert!(metrics.contains(r#"emails_sent_total{provider="postmark",template="confirmation"}"#));
+    assert!(metrics.contains(r#"subscribers{status="pending_confirmation"}"#));
+}
+
+#[tokio::test]
+async fn rejected_bot_submissions_are_counted() {
+    let app = spawn_app().await;
+    app.post_subscriptions(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&website=http%3A%2F%2Fspam.example".into(),
+    )
+    .await;
+
+    let metrics = scrape(&app).await;
+
+    assert!(metrics.contains(r#"bot_submissions_rejected_total{signal="honeypot"}"#));
+}
+
+#[tokio::test]
+async fn metrics_are_not_served_on_the_application_port_by_default() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/metrics", &app.address))
+        .await
+        .unwrap();
+
+    assert_eq!(404, response.status().as_u16());
+    assert!(scrape(&app).await.contains("db_pool_connections"));
+}
+
+#[tokio::test]
+async fn metrics_on_the_application_port_are_served_to_admins_only() {
+    let app = spawn_app_with(|c| c.metrics.port = None).await;
+
+    let response = reqwest::get(format!("{}/metrics", &app.address))
+        .await
+        .unwrap();
+    assert_eq!(401, response.status().as_u16());
+
+    let response = app
+        .admin_request(Method::GET, "/metrics")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, response.status().as_u16());
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("db_pool_connections"));
+}
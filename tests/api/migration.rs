@@ -0,0 +1,70 @@
+use sqlx::{Connection, Executor, PgConnection};
+use uuid::Uuid;
+use zero2prod::{
+    configuration::{get_configuration, DatabaseSettings},
+    migration::{migrate_database, MigrationError, MigrationMode},
+};
+
+use crate::helpers::spawn_app_with;
+
+/// A database that exists but has never been migrated.
+async fn empty_database() -> DatabaseSettings {
+    let mut settings = get_configuration()
+        .expect("Failed to read config.")
+        .database;
+    settings.database_name = Uuid::new_v4().to_string();
+    PgConnection::connect_with(&settings.without_db())
+        .await
+        .expect("Failed to connect to Postgres.")
+        .execute(format!(r#"CREATE DATABASE "{}";"#, settings.database_name).as_str())
+        .await
+        .expect("Failed to create database.");
+    settings
+}
+
+#[tokio::test]
+async fn check_mode_refuses_a_database_that_is_behind() {
+    let settings = empty_database().await;
+
+    let outcome = migrate_database(&settings, MigrationMode::Check).await;
+
+    assert!(matches!(outcome, Err(MigrationError::Pending(_))));
+    // Checking leaves the schema alone.
+    let migrations_table: Option<String> =
+        sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations')::text")
+            .fetch_one(
+                &mut PgConnection::connect_with(&settings.with_db())
+                    .await
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+    assert_eq!(migrations_table, None);
+}
+
+#[tokio::test]
+async fn concurrent_instances_apply_migrations_once() {
+    let settings = empty_database().await;
+
+    let (first, second) = tokio::join!(
+        migrate_database(&settings, MigrationMode::Apply),
+        migrate_database(&settings, MigrationMode::Apply),
+    );
+
+    first.unwrap();
+    second.unwrap();
+    migrate_database(&settings, MigrationMode::Check)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn the_application_boots_in_check_mode_once_migrated() {
+    let app = spawn_app_with(|c| c.application.run_migrations = MigrationMode::Check).await;
+
+    let response = reqwest::get(format!("{}/health_check", app.address))
+        .await
+        .unwrap();
+
+    assert_eq!(200, response.status().as_u16());
+}
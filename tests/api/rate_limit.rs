@@ -0,0 +1,101 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::configuration::RateLimitStoreKind;
+
+use crate::helpers::{spawn_app_with, TestApp};
+
+async fn mount_email_server(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+#[tokio::test]
+async fn subscribe_is_throttled_per_client_ip() {
+    let app = spawn_app_with(|c| c.rate_limit.per_ip.max_requests = 2).await;
+
+    for _ in 0..2 {
+        let response = app.post_subscriptions("name=le%20guin".into()).await;
+        assert_eq!(422, response.status().as_u16());
+    }
+    let response = app.post_subscriptions("name=le%20guin".into()).await;
+
+    assert_eq!(429, response.status().as_u16());
+    let retry_after: u64 = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 0 && retry_after <= 60);
+}
+
+#[tokio::test]
+async fn subscribe_is_throttled_per_target_email() {
+    let app = spawn_app_with(|c| c.rate_limit.per_email.max_requests = 1).await;
+    mount_email_server(&app).await;
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    assert_eq!(200, response.status().as_u16());
+
+    let response = app
+        .post_subscriptions("name=someone%20else&email=URSULA_LE_GUIN%40gmail.com".into())
+        .await;
+    assert_eq!(429, response.status().as_u16());
+    assert!(response.headers().contains_key("Retry-After"));
+}
+
+#[tokio::test]
+async fn the_postgres_store_shares_counters_through_the_database() {
+    let app = spawn_app_with(|c| {
+        c.rate_limit.store = RateLimitStoreKind::Postgres;
+        c.rate_limit.per_email.max_requests = 1;
+    })
+    .await;
+    mount_email_server(&app).await;
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    assert_eq!(
+        200,
+        app.post_subscriptions(body.into()).await.status().as_u16()
+    );
+    assert_eq!(
+        429,
+        app.post_subscriptions(body.into()).await.status().as_u16()
+    );
+
+    let hits = sqlx::query!("SELECT hits FROM rate_limits WHERE key LIKE 'email:%'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .hits;
+    assert_eq!(hits, 2);
+}
+
+#[tokio::test]
+async fn the_postgres_store_is_checked_before_a_transaction_holds_a_connection() {
+    let app = spawn_app_with(|c| {
+        c.rate_limit.store = RateLimitStoreKind::Postgres;
+        c.rate_limit.per_email.max_requests = 1;
+        c.database.max_connections = 1;
+        c.database.acquire_timeout_milliseconds = 500;
+    })
+    .await;
+    mount_email_server(&app).await;
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    assert_eq!(
+        200,
+        app.post_subscriptions(body.into()).await.status().as_u16()
+    );
+    // The limiter would fail open if it could not get a connection.
+    assert_eq!(
+        429,
+        app.post_subscriptions(body.into()).await.status().as_u16()
+    );
+}
@@ -0,0 +1,55 @@
+use secrecy::Secret;
+use zero2prod::configuration::Settings;
+
+use crate::helpers::{spawn_app, spawn_app_with};
+
+#[tokio::test]
+async fn reloaded_rate_limits_apply_to_the_next_requests() {
+    let app = spawn_app_with(|c| c.rate_limit.per_ip.max_requests = 100).await;
+    let mut settings = Settings::clone(&app.reloader.current());
+    settings.rate_limit.per_ip.max_requests = 1;
+
+    let refused = app.reloader.apply(settings);
+
+    assert!(refused.is_empty());
+    let response = app.post_subscriptions("name=le%20guin".into()).await;
+    assert_eq!(422, response.status().as_u16());
+    let response = app.post_subscriptions("name=le%20guin".into()).await;
+    assert_eq!(429, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn structural_changes_are_refused_and_the_rest_is_applied() {
+    let app = spawn_app().await;
+    let mut settings = Settings::clone(&app.reloader.current());
+    let database_host = settings.database.host.clone();
+    settings.database.host = "db.example.com".into();
+    settings.application.port += 1;
+    settings.health.timeout_milliseconds += 1;
+    let health_timeout = settings.health.timeout_milliseconds;
+
+    let refused = app.reloader.apply(settings);
+
+    assert_eq!(refused, ["application.port", "database.host"]);
+    let current = app.reloader.current();
+    assert_eq!(current.database.host, database_host);
+    assert_eq!(current.health.timeout_milliseconds, health_timeout);
+    let response = reqwest::get(format!("{}/health/ready", &app.address))
+        .await
+        .unwrap();
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn changes_to_any_other_setting_or_secret_are_refused() {
+    let app = spawn_app().await;
+    let mut settings = Settings::clone(&app.reloader.current());
+    settings.bot_defence.min_fill_seconds += 1;
+    settings.csrf.secret = Secret::new("another-secret".into());
+    settings.email_client.authorization_token =
+        Secret::new("${vault:zero2prod/postmark#token}".into());
+
+    let refused = app.reloader.apply(settings);
+
+    assert_eq!(refused, ["bot_defence.min_fill_seconds", "csrf.secret"]);
+}
@@ -0,0 +1,77 @@
+use wiremock::{
+    matchers::{header, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, TestApp};
+
+const INVALID_FORM: &str = "name=le%20guin&email=ursula_le_guingmail.com";
+
+async fn post_subscriptions_with_id(app: &TestApp, body: &str, id: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("X-Request-Id", id)
+        .body(body.to_owned())
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn a_request_id_is_generated_and_returned_when_none_is_sent() {
+    let app = spawn_app().await;
+
+    let response = app.post_subscriptions(INVALID_FORM.into()).await;
+
+    let request_id = response.headers()["X-Request-Id"]
+        .to_str()
+        .unwrap()
+        .to_owned();
+    assert!(uuid::Uuid::parse_str(&request_id).is_ok());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["request_id"], request_id);
+}
+
+#[tokio::test]
+async fn an_incoming_request_id_is_echoed_and_used_in_error_bodies() {
+    let app = spawn_app().await;
+
+    let response = post_subscriptions_with_id(&app, INVALID_FORM, "lb-1234.abc").await;
+
+    assert_eq!(response.headers()["X-Request-Id"], "lb-1234.abc");
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["request_id"], "lb-1234.abc");
+}
+
+#[tokio::test]
+async fn an_invalid_incoming_request_id_is_replaced() {
+    let app = spawn_app().await;
+
+    let response =
+        post_subscriptions_with_id(&app, INVALID_FORM, "<script>alert(1)</script>").await;
+
+    let request_id = response.headers()["X-Request-Id"].to_str().unwrap();
+    assert!(uuid::Uuid::parse_str(request_id).is_ok());
+}
+
+#[tokio::test]
+async fn the_request_id_is_forwarded_to_the_email_provider() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .and(header("X-Request-Id", "lb-1234.abc"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = post_subscriptions_with_id(
+        &app,
+        "name=le%20guin&email=ursula_le_guin%40gmail.com",
+        "lb-1234.abc",
+    )
+    .await;
+
+    assert_eq!(200, response.status().as_u16());
+}
@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn insert_confirmed_subscribers(app: &TestApp, emails: &[&str]) {
+    for email in emails {
+        sqlx::query!(
+            r#"
+            INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+            VALUES ($1, $2, 'reader', $3, 'confirmed')
+            "#,
+            Uuid::new_v4(),
+            email,
+            Utc::now(),
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn in_flight_requests_are_drained_on_shutdown() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(300)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let subscribe = tokio::spawn(
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions", &app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+            .send(),
+    );
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    app.shutdown.trigger();
+
+    // The confirmation email is not cut off halfway through...
+    let response = subscribe.await.unwrap().unwrap();
+    assert_eq!(200, response.status().as_u16());
+
+    // ...but no new connections are accepted.
+    let refused = reqwest::get(format!("{}/health_check", &app.address)).await;
+    assert!(refused.is_err());
+}
+
+#[tokio::test]
+async fn newsletter_delivery_stops_between_emails_on_shutdown() {
+    let app = spawn_app().await;
+    insert_confirmed_subscribers(&app, &["ursula@example.com", "octavia@example.com"]).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(300)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let publish = tokio::spawn(
+        reqwest::Client::new()
+            .post(format!("{}/newsletters", &app.address))
+            .json(&serde_json::json!({
+                "title": "Newsletter title",
+                "content": {"text": "Plain text", "html": "<p>HTML</p>"}
+            }))
+            .send(),
+    );
+    // Admin authentication takes a while, wait for the first email.
+    while app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .is_empty()
+    {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    app.shutdown.trigger();
+
+    // The email being sent is finished, the other one is not sent.
+    let response = publish.await.unwrap().unwrap();
+    assert_eq!(503, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["type"], "/problems/delivery-interrupted");
+}
@@ -123,4 +123,155 @@ async fn subscribe_fails_if_there_is_a_fatal_db_error() {
 
     let response = app.post_subscriptions(body.into()).await;
     assert_eq!(500, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    // The cause chain stays in the logs.
+    assert!(!body.to_string().contains("subscription_token"));
+}
+
+#[tokio::test]
+async fn errors_are_returned_as_problem_details() {
+    let app = spawn_app().await;
+    let response = app
+        .post_subscriptions_json(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guingmail.com"
+        }))
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+    assert_eq!(
+        "application/problem+json",
+        response.headers()["Content-Type"]
+    );
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["type"], "/problems/validation-error");
+    assert_eq!(body["status"], 400);
+    assert!(body["title"].is_string());
+    assert!(body["detail"].is_string());
+    assert!(body["request_id"].is_string());
+}
+
+#[tokio::test]
+async fn subscribe_rejects_disposable_email_domains() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula%40mailinator.com".into())
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["errors"][0]["code"], "disposable_domain");
+}
+
+#[tokio::test]
+async fn every_invalid_field_is_reported() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriptions_json(&serde_json::json!({
+            "name": "",
+            "email": "ursula_le_guingmail.com"
+        }))
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["errors"][0]["code"], "invalid_name");
+    assert_eq!(body["errors"][1]["code"], "invalid_email");
+}
+
+#[tokio::test]
+async fn form_posts_get_field_errors_too() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guingmail.com";
+
+    let response = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body["detail"].is_string());
+    assert_eq!(body["errors"][0]["field"], "email");
+    assert_eq!(body["errors"][0]["code"], "invalid_email");
+}
+
+#[tokio::test]
+async fn subscribe_accepts_a_json_body() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions_json(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com"
+        }))
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT email, name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
+    assert_eq!(saved.name, "le guin");
+}
+
+#[tokio::test]
+async fn subscribe_returns_structured_errors_to_json_clients() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriptions_json(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guingmail.com"
+        }))
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["errors"][0]["field"], "email");
+    assert_eq!(body["errors"][0]["code"], "invalid_email");
+    assert!(body["errors"][0]["message"].is_string());
+}
+
+#[tokio::test]
+async fn form_posts_get_json_errors_when_they_accept_json() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Accept", "application/json")
+        .body("name=&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["errors"][0]["field"], "name");
+    assert_eq!(body["errors"][0]["code"], "invalid_name");
+}
+
+#[tokio::test]
+async fn subscribe_returns_a_422_when_json_fields_are_missing() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriptions_json(&serde_json::json!({ "name": "le guin" }))
+        .await;
+
+    assert_eq!(422, response.status().as_u16());
 }
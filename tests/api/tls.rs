@@ -0,0 +1,136 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use reqwest::{Certificate, Version};
+use uuid::Uuid;
+use zero2prod::configuration::TlsSettings;
+
+use crate::helpers::{spawn_app_with, TestApp};
+
+/// Write a new self-signed certificate for 127.0.0.1 to `directory`,
+/// returning it for clients to trust.
+fn write_certificate(directory: &Path) -> Certificate {
+    let certified = rcgen::generate_simple_self_signed(vec!["127.0.0.1".into()]).unwrap();
+    std::fs::write(directory.join("tls.crt"), certified.cert.pem()).unwrap();
+    std::fs::write(
+        directory.join("tls.key"),
+        certified.key_pair.serialize_pem(),
+    )
+    .unwrap();
+    Certificate::from_pem(certified.cert.pem().as_bytes()).unwrap()
+}
+
+fn certificate_directory() -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("zero2prod-tls-{}", Uuid::new_v4()));
+    std::fs::create_dir(&directory).unwrap();
+    directory
+}
+
+async fn spawn_https_app(directory: &Path, redirect_port: Option<u16>) -> TestApp {
+    spawn_app_with(|c| {
+        c.application.base_url = "https://newsletter.example.com".parse().unwrap();
+        c.application.tls = Some(TlsSettings {
+            certificate_path: directory.join("tls.crt"),
+            private_key_path: directory.join("tls.key"),
+            redirect_port,
+        });
+    })
+    .await
+}
+
+fn client_trusting(certificate: Certificate) -> reqwest::Client {
+    reqwest::Client::builder()
+        .use_rustls_tls()
+        .tls_built_in_root_certs(false)
+        .add_root_certificate(certificate)
+        .build()
+        .unwrap()
+}
+
+async fn health_check(client: &reqwest::Client, app: &TestApp) -> reqwest::Result<Version> {
+    let response = client
+        .get(format!("https://127.0.0.1:{}/health_check", app.port))
+        .send()
+        .await?;
+    assert_eq!(200, response.status().as_u16());
+    Ok(response.version())
+}
+
+#[tokio::test]
+async fn https_is_served_over_http2_when_negotiated() {
+    let directory = certificate_directory();
+    let certificate = write_certificate(&directory);
+    let app = spawn_https_app(&directory, None).await;
+
+    let version = health_check(&client_trusting(certificate), &app)
+        .await
+        .unwrap();
+
+    assert_eq!(version, Version::HTTP_2);
+    let untrusting = client_trusting(write_certificate(&certificate_directory()));
+    assert!(health_check(&untrusting, &app).await.is_err());
+}
+
+#[tokio::test]
+async fn a_renewed_certificate_is_served_without_a_restart() {
+    let directory = certificate_directory();
+    write_certificate(&directory);
+    let app = spawn_https_app(&directory, None).await;
+
+    let renewed = client_trusting(write_certificate(&directory));
+
+    let mut served = false;
+    for _ in 0..50 {
+        if health_check(&renewed, &app).await.is_ok() {
+            served = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(served, "The renewed certificate was never served");
+}
+
+#[tokio::test]
+async fn a_certificate_without_its_key_is_not_served() {
+    let directory = certificate_directory();
+    let current = write_certificate(&directory);
+    let app = spawn_https_app(&directory, None).await;
+
+    // Only the certificate is renewed, the key stays the old one.
+    let renewal = certificate_directory();
+    write_certificate(&renewal);
+    std::fs::copy(renewal.join("tls.crt"), directory.join("tls.crt")).unwrap();
+
+    for _ in 0..20 {
+        // A new client each time, so that every check is a new handshake.
+        health_check(&client_trusting(current.clone()), &app)
+            .await
+            .expect("The current certificate stopped being served");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+#[tokio::test]
+async fn plain_http_requests_are_redirected_to_the_base_url() {
+    let directory = certificate_directory();
+    write_certificate(&directory);
+    let app = spawn_https_app(&directory, Some(0)).await;
+
+    let response = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .get(format!(
+            "http://127.0.0.1:{}/subscriptions/confirm?subscription_token=abc",
+            app.redirect_port.unwrap()
+        ))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(308, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Location"],
+        "https://newsletter.example.com/subscriptions/confirm?subscription_token=abc"
+    );
+}
@@ -0,0 +1,54 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::spawn_app;
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+#[tokio::test]
+async fn the_callers_trace_is_propagated_to_the_email_provider() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("traceparent", format!("00-{TRACE_ID}-00f067aa0ba902b7-01"))
+        .form(&[("name", "le guin"), ("email", "ursula_le_guin@gmail.com")])
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let traceparent = email_request.headers["traceparent"].to_str().unwrap();
+    let fields: Vec<_> = traceparent.split('-').collect();
+    assert_eq!(fields[1], TRACE_ID);
+    // Our own span is the parent, not the caller's.
+    assert_ne!(fields[2], "00f067aa0ba902b7");
+}
+
+#[tokio::test]
+async fn requests_without_a_traceparent_start_a_new_trace() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let traceparent = email_request.headers["traceparent"].to_str().unwrap();
+    assert!(traceparent.starts_with("00-"));
+    assert!(!traceparent.contains(TRACE_ID));
+}